@@ -1,7 +1,7 @@
 use std::path::PathBuf;
 use clap::{App, ArgMatches, SubCommand};
 use mdbook::MDBook;
-use mdbook::errors::Result;
+use mdbook::errors::*;
 use {get_book_dir, open};
 
 // Create clap subcommand arguments
@@ -13,6 +13,10 @@ pub fn make_subcommand<'a, 'b>() -> App<'a, 'b> {
             "-d, --dest-dir=[dest-dir] 'The output directory for your book{n}(Defaults to ./book \
              when omitted)'",
         )
+        .arg_from_usage(
+            "-j, --jobs=[jobs] 'The number of threads to render with{n}(Defaults to the number \
+             of CPUs)'",
+        )
         .arg_from_usage(
             "[dir] 'A directory for your book{n}(Defaults to Current Directory when omitted)'",
         )
@@ -27,6 +31,12 @@ pub fn execute(args: &ArgMatches) -> Result<()> {
         book.config.build.build_dir = PathBuf::from(dest_dir);
     }
 
+    if let Some(jobs) = args.value_of("jobs") {
+        let jobs = jobs.parse()
+            .chain_err(|| format!("Invalid number of jobs, {:?}", jobs))?;
+        book.config.build.jobs = Some(jobs);
+    }
+
     book.build()?;
 
     if args.is_present("open") {
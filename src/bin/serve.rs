@@ -54,6 +54,8 @@ pub fn execute(args: &ArgMatches) -> Result<()> {
     let livereload_url = format!("ws://{}:{}", public_address, ws_port);
     book.config
         .set("output.html.livereload-url", &livereload_url)?;
+    // Only re-render what changed between reloads
+    book.config.build.incremental = true;
 
     book.build()?;
 
@@ -91,6 +93,7 @@ pub fn execute(args: &ArgMatches) -> Result<()> {
         let result = MDBook::load(&book_dir)
             .and_then(move |mut b| {
                 b.config.set("output.html.livereload-url", &livereload_url)?;
+                b.config.build.incremental = true;
                 Ok(b)
             })
             .and_then(|b| b.build());
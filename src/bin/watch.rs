@@ -7,6 +7,7 @@ use std::sync::mpsc::channel;
 use clap::{App, ArgMatches, SubCommand};
 use mdbook::MDBook;
 use mdbook::utils;
+use mdbook::utils::fs::{ExcludedFiles, IGNORE_FILE};
 use mdbook::errors::Result;
 use {get_book_dir, open};
 
@@ -23,7 +24,8 @@ pub fn make_subcommand<'a, 'b>() -> App<'a, 'b> {
 // Watch command implementation
 pub fn execute(args: &ArgMatches) -> Result<()> {
     let book_dir = get_book_dir(args);
-    let book = MDBook::load(&book_dir)?;
+    let mut book = MDBook::load(&book_dir)?;
+    book.config.build.incremental = true;
 
     if args.is_present("open") {
         book.build()?;
@@ -32,7 +34,10 @@ pub fn execute(args: &ArgMatches) -> Result<()> {
 
     trigger_on_change(&book, |path, book_dir| {
         info!("File changed: {:?}\nBuilding book...\n", path);
-        let result = MDBook::load(&book_dir).and_then(|b| b.build());
+        let result = MDBook::load(&book_dir).and_then(|mut b| {
+            b.config.build.incremental = true;
+            b.build()
+        });
 
         if let Err(e) = result {
             error!("Unable to build the book");
@@ -68,20 +73,61 @@ where
         ::std::process::exit(1);
     };
 
+    // The book's theme directory and the parent theme it is layered over.
+    // When `theme` names a built-in theme the book's files are in `theme/`.
     let _ = watcher.watch(book.theme_dir(), Recursive);
+    if book.theme_dir() != book.root.join("theme") {
+        let _ = watcher.watch(book.root.join("theme"), Recursive);
+    }
+    if let Some(parent) = book.config.html_config().and_then(|html| html.parent_theme) {
+        let _ = watcher.watch(book.root.join(parent), Recursive);
+    }
 
     // Add the book.toml file to the watcher if it exists
     let _ = watcher.watch(book.root.join("book.toml"), NonRecursive);
 
+    // Changes to files the book leaves out shouldn't cause a rebuild
+    let mut excluded = match ExcludedFiles::new(&book.source_dir(), &book.config.build.exclude) {
+        Ok(excluded) => excluded,
+        Err(e) => {
+            error!("Unable to read the list of excluded files");
+            utils::log_backtrace(&e);
+            ::std::process::exit(1)
+        }
+    };
+
     info!("Listening for changes...");
 
     for event in rx.iter() {
         debug!("Received filesystem event: {:?}", event);
         match event {
             Create(path) | Write(path) | Remove(path) | Rename(_, path) => {
+                // The files which say what's excluded always count
+                let is_config = path.file_name().map_or(false, |name| name == IGNORE_FILE)
+                    || path.ends_with("book.toml");
+                if !is_config && excluded.is_excluded(&path) {
+                    debug!("Ignoring change to excluded file {:?}", path);
+                    continue;
+                }
                 closure(&path, &book.root);
+
+                // The change may have been to what's excluded
+                match excluded_files(&book.root) {
+                    Ok(reloaded) => excluded = reloaded,
+                    Err(e) => {
+                        warn!("Unable to reload the list of excluded files");
+                        utils::log_backtrace(&e);
+                    }
+                }
             }
             _ => {}
         }
     }
 }
+
+/// The files changes to which are ignored, going by the book's current
+/// configuration.
+fn excluded_files(book_root: &Path) -> Result<ExcludedFiles> {
+    let book = MDBook::load(book_root)?;
+    ExcludedFiles::new(&book.source_dir(), &book.config.build.exclude)
+}
@@ -127,6 +127,9 @@ impl BookBuilder {
         let mut index = File::create(themedir.join("index.hbs"))?;
         index.write_all(theme::INDEX)?;
 
+        let mut redirect = File::create(themedir.join("redirect.hbs"))?;
+        redirect.write_all(theme::REDIRECT)?;
+
         let mut css = File::create(themedir.join("book.css"))?;
         css.write_all(theme::CSS)?;
 
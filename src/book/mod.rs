@@ -13,13 +13,15 @@ pub use self::book::{load_book, Book, BookItem, BookItems, Chapter};
 pub use self::summary::{parse_summary, Link, SectionNumber, Summary, SummaryItem};
 pub use self::init::BookBuilder;
 
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::path::{Component, PathBuf};
 use std::io::Write;
 use std::process::Command;
 use tempfile::Builder as TempFileBuilder;
 use toml::Value;
 
 use utils;
+use utils::git::LastModified;
 use renderer::{CmdRenderer, HtmlHandlebars, RenderContext, Renderer};
 use preprocess::{
     LinkPreprocessor,
@@ -162,18 +164,64 @@ impl MDBook {
             preprocessor.run(&preprocess_ctx, &mut preprocessed_book)?;
         }
 
+        let last_modified = self.last_modified(&preprocessed_book);
+
         for renderer in &self.renderers {
             info!("Running the {} backend", renderer.name());
-            self.run_renderer(&preprocessed_book, renderer.as_ref())?;
+            self.run_renderer(&preprocessed_book, &last_modified, renderer.as_ref())?;
         }
 
         Ok(())
     }
 
-    fn run_renderer(&self, preprocessed_book: &Book, renderer: &Renderer) -> Result<()> {
+    /// Ask git when each chapter was last changed. Books which aren't in a
+    /// git repository (or machines without git) simply don't get any dates.
+    fn last_modified(&self, book: &Book) -> BTreeMap<PathBuf, LastModified> {
+        let mut last_modified = BTreeMap::new();
+        if !self.config.build.last_modified {
+            return last_modified;
+        }
+
+        let src_dir = self.source_dir();
+        let files = match utils::git::last_modified(&src_dir) {
+            Some(files) => files,
+            None => {
+                debug!("Not in a git repository, chapters won't get last modified dates");
+                return last_modified;
+            }
+        };
+
+        for item in book.iter() {
+            if let BookItem::Chapter(ref ch) = *item {
+                // git lists files without a leading `./`
+                let source = utils::fs::chapter_source_path(&src_dir, &ch.path)
+                    .components()
+                    .filter(|component| *component != Component::CurDir)
+                    .collect::<PathBuf>();
+                if let Some(modified) = files.get(&source) {
+                    last_modified.insert(ch.path.clone(), modified.clone());
+                }
+            }
+        }
+
+        last_modified
+    }
+
+    fn run_renderer(
+        &self,
+        preprocessed_book: &Book,
+        last_modified: &BTreeMap<PathBuf, LastModified>,
+        renderer: &dyn Renderer,
+    ) -> Result<()> {
         let name = renderer.name();
         let build_dir = self.build_dir_for(name);
-        if build_dir.exists() {
+        if self.config.build.incremental {
+            debug!(
+                "Keeping the previous output for the \"{}\" renderer ({})",
+                name,
+                build_dir.display()
+            );
+        } else if build_dir.exists() {
             debug!(
                 "Cleaning build dir for the \"{}\" renderer ({})",
                 name,
@@ -184,12 +232,13 @@ impl MDBook {
                 .chain_err(|| "Unable to clear output directory")?;
         }
 
-        let render_context = RenderContext::new(
+        let mut render_context = RenderContext::new(
             self.root.clone(),
             preprocessed_book.clone(),
             self.config.clone(),
             build_dir,
         );
+        render_context.last_modified = last_modified.clone();
 
         renderer
             .render(&render_context)
@@ -198,8 +247,11 @@ impl MDBook {
 
     /// You can change the default renderer to another one by using this method.
     /// The only requirement is for your renderer to implement the [`Renderer`
-    /// trait](../renderer/trait.Renderer.html)
+    /// trait](../renderer/trait.Renderer.html). A renderer with the same name
+    /// as one the book already uses (e.g. an `HtmlHandlebars` with extra
+    /// helpers) takes its place.
     pub fn with_renderer<R: Renderer + 'static>(&mut self, renderer: R) -> &mut Self {
+        self.renderers.retain(|existing| existing.name() != renderer.name());
         self.renderers.push(Box::new(renderer));
         self
     }
@@ -50,6 +50,7 @@
 
 #![deny(missing_docs)]
 
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
 use std::fs::File;
 use std::io::Read;
@@ -383,6 +384,18 @@ pub struct BuildConfig {
     pub create_missing: bool,
     /// Which preprocessors should be applied
     pub preprocess: Option<Vec<String>>,
+    /// Should the build directory be kept between builds so renderers can
+    /// skip regenerating output which hasn't changed? Default: `false`.
+    pub incremental: bool,
+    /// How many threads renderers may use. If `None`, one per CPU is used.
+    pub jobs: Option<usize>,
+    /// Files in the source directory (matched with `.gitignore`-style globs)
+    /// which shouldn't be copied into the book or trigger a rebuild when they
+    /// change.
+    pub exclude: Vec<String>,
+    /// Should `git` be asked when each chapter was last changed, so
+    /// renderers can show it? Default: `true`.
+    pub last_modified: bool,
 }
 
 impl Default for BuildConfig {
@@ -391,6 +404,10 @@ impl Default for BuildConfig {
             build_dir: PathBuf::from("book"),
             create_missing: true,
             preprocess: None,
+            incremental: false,
+            jobs: None,
+            exclude: Vec::new(),
+            last_modified: true,
         }
     }
 }
@@ -401,10 +418,24 @@ impl Default for BuildConfig {
 pub struct HtmlConfig {
     /// The theme directory, if specified.
     pub theme: Option<PathBuf>,
+    /// A theme directory the book's own theme is layered over, if specified.
+    pub parent_theme: Option<PathBuf>,
+    /// The color theme readers get until they pick one. Default: `light`.
+    pub default_theme: Option<String>,
+    /// The color theme readers whose system prefers a dark color scheme get
+    /// until they pick one. Default: `navy`.
+    pub preferred_dark_theme: Option<String>,
+    /// Color themes readers can pick on top of the built-in ones, by the id
+    /// they are selected with (which is also their CSS class).
+    pub color_schemes: BTreeMap<String, ColorScheme>,
     /// Use "smart quotes" instead of the usual `"` character.
     pub curly_quotes: bool,
     /// Should mathjax be enabled?
     pub mathjax_support: bool,
+    /// How math gets rendered when `mathjax_support` is enabled.
+    pub math: Math,
+    /// How the images chapters refer to are dealt with.
+    pub images: Images,
     /// An optional google analytics code.
     pub google_analytics: Option<String>,
     /// Additional CSS stylesheets to include in the rendered page's `<head>`.
@@ -412,6 +443,9 @@ pub struct HtmlConfig {
     /// Additional JS scripts to include at the bottom of the rendered page's
     /// `<body>`.
     pub additional_js: Vec<PathBuf>,
+    /// Files in the source directory which the HTML renderer shouldn't copy
+    /// into the book, on top of those excluded by `build.exclude`.
+    pub copy_exclude: Vec<String>,
     /// Playpen settings.
     pub playpen: Playpen,
     /// This is used as a bit of a workaround for the `mdbook serve` command.
@@ -424,8 +458,50 @@ pub struct HtmlConfig {
     pub livereload_url: Option<String>,
     /// Should section labels be rendered?
     pub no_section_label: bool,
+    /// Which sections of the sidebar start out collapsed.
+    pub fold: Fold,
+    /// Print settings.
+    pub print: Print,
     /// Search settings. If `None`, the default will be used.
     pub search: Option<Search>,
+    /// The URL the book will be deployed to (e.g.
+    /// `https://example.com/book/`), used for canonical links, Open Graph
+    /// tags and the sitemap.
+    pub site_url: Option<String>,
+    /// A URL for editing a chapter's source (e.g.
+    /// `https://github.com/user/book/edit/master/{path}`), where `{path}` is
+    /// replaced by the chapter's path relative to the root of the book.
+    pub edit_url_template: Option<String>,
+    /// Should links to other chapters which don't lead anywhere fail the
+    /// build instead of just printing a warning?
+    pub deny_broken_links: bool,
+    /// Redirects from old locations (e.g. `/old/chapter.html`) to where the
+    /// content lives now.
+    pub redirect: BTreeMap<String, String>,
+    /// Which chapters (matched with globs like `appendix/*.md`) get rendered
+    /// with one of the theme's extra page templates instead of `index.hbs`,
+    /// by template name.
+    pub templates: BTreeMap<String, Vec<String>>,
+    /// Where code blocks get syntax highlighted.
+    pub highlight: Highlight,
+    /// Make the book work without a network connection: bundle the fonts,
+    /// leave out anything which talks to other websites and add a service
+    /// worker which caches every file.
+    pub offline: bool,
+    /// Also render the whole book into a single, self-contained `book.html`
+    /// with every stylesheet, script, font and image embedded in it.
+    pub single_file: bool,
+    /// Put a hash of each stylesheet's and script's contents in its name
+    /// (e.g. `book.3f2a9c81.css`), so caches never hand out old copies.
+    pub hash_files: bool,
+    /// Strip comments and whitespace out of the rendered pages and the
+    /// theme's stylesheets and scripts, and round the scores in the search
+    /// index.
+    pub minify: bool,
+    /// Write gzipped and brotli-compressed copies of every text file next to
+    /// it (e.g. `book.css.gz` and `book.css.br`), for web servers which can
+    /// serve those directly.
+    pub precompress: bool,
 }
 
 impl HtmlConfig {
@@ -459,6 +535,144 @@ impl Default for Playpen {
     }
 }
 
+/// Configuration for the print page of the HTML renderer.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Print {
+    /// Should the print page (`print.html`) be generated? Default: `true`.
+    pub enable: bool,
+}
+
+impl Default for Print {
+    fn default() -> Print {
+        Print { enable: true }
+    }
+}
+
+/// Configuration for collapsing sections of the sidebar.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Fold {
+    /// Can sections be collapsed, and do they start out that way? Default:
+    /// `false`.
+    pub enable: bool,
+    /// How many levels of the sidebar are expanded, besides the ones leading
+    /// to the current chapter. `0` (the default) collapses every section.
+    pub level: u8,
+}
+
+/// A color theme declared in `[output.html.color-schemes]`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct ColorScheme {
+    /// The name shown in the theme menu. Defaults to the scheme's id.
+    pub name: Option<String>,
+    /// The stylesheet with the scheme's colors, relative to the book's root.
+    pub css: PathBuf,
+    /// Is it a dark scheme? Dark schemes get the dark code highlighting and
+    /// editor theme. Default: `false`.
+    pub dark: bool,
+}
+
+/// Configuration for how the HTML renderer deals with math.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Math {
+    /// Where MathJax comes from, or whether to use it at all. Default: `Cdn`.
+    pub mode: MathMode,
+    /// A directory containing MathJax (the one with `MathJax.js` in it),
+    /// which gets copied into the book when vendoring MathJax.
+    pub mathjax_dir: Option<PathBuf>,
+    /// The command used to render inline math while building the book. It
+    /// is given the TeX on `stdin` and should print HTML (or MathML).
+    pub command: Option<String>,
+    /// The command used to render display math, if it differs from
+    /// `command`.
+    pub display_command: Option<String>,
+}
+
+/// The ways math can be rendered.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MathMode {
+    /// Load MathJax from a CDN.
+    Cdn,
+    /// Copy MathJax from `mathjax_dir` into the book and load it from there.
+    Vendored,
+    /// Render every equation while building the book, using `command`.
+    Command,
+}
+
+impl Default for MathMode {
+    fn default() -> MathMode {
+        MathMode::Cdn
+    }
+}
+
+/// Configuration for how the HTML renderer deals with images, and other
+/// files in the source directory.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Images {
+    /// Give images `width` and `height` attributes, so pages don't jump
+    /// around while they load.
+    pub dimensions: bool,
+    /// Only load images once they're about to be scrolled into view.
+    pub lazy_loading: bool,
+    /// What to do with files in the source directory which no chapter refers
+    /// to. Default: `Copy`.
+    pub unreferenced: Unreferenced,
+    /// Fail the build when a chapter embeds an image which doesn't exist.
+    /// Turn it off to only get a warning about each one. Default: `true`.
+    pub deny_missing: bool,
+}
+
+impl Default for Images {
+    fn default() -> Images {
+        Images {
+            dimensions: false,
+            lazy_loading: false,
+            unreferenced: Unreferenced::default(),
+            deny_missing: true,
+        }
+    }
+}
+
+/// What can be done with files no chapter refers to.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Unreferenced {
+    /// Copy them into the book anyway.
+    Copy,
+    /// Copy them, but print a warning about each one.
+    Warn,
+    /// Leave them out of the book.
+    Exclude,
+}
+
+impl Default for Unreferenced {
+    fn default() -> Unreferenced {
+        Unreferenced::Copy
+    }
+}
+
+/// Where code blocks get syntax highlighted.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Highlight {
+    /// In the reader's browser, using `highlight.js`. This is the default.
+    Client,
+    /// While the book is being rendered, so readers don't need to download
+    /// `highlight.js`.
+    Server,
+}
+
+impl Default for Highlight {
+    fn default() -> Highlight {
+        Highlight::Client
+    }
+}
+
 /// Configuration of the search functionality of the HTML renderer.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(default, rename_all = "kebab-case")]
@@ -552,16 +766,44 @@ mod tests {
         build-dir = "outputs"
         create-missing = false
         preprocess = ["first_preprocessor", "second_preprocessor"]
+        exclude = ["*.swp"]
+        last-modified = false
 
         [output.html]
         theme = "./themedir"
+        parent-theme = "../shared-theme"
         curly-quotes = true
         google-analytics = "123456"
         additional-css = ["./foo/bar/baz.css"]
+        copy-exclude = ["fixtures/"]
+        highlight = "server"
+        default-theme = "rust"
+        preferred-dark-theme = "solarized"
 
         [output.html.playpen]
         editable = true
         editor = "ace"
+
+        [output.html.math]
+        mode = "vendored"
+        mathjax-dir = "./mathjax"
+
+        [output.html.fold]
+        enable = true
+        level = 1
+
+        [output.html.images]
+        dimensions = true
+        unreferenced = "warn"
+        deny-missing = false
+
+        [output.html.redirect]
+        "/old/chapter.html" = "/new/chapter.html"
+
+        [output.html.color-schemes.solarized]
+        name = "Solarized"
+        css = "theme/solarized.css"
+        dark = true
         "#;
 
     #[test]
@@ -583,17 +825,57 @@ mod tests {
                 "first_preprocessor".to_string(),
                 "second_preprocessor".to_string(),
             ]),
+            incremental: false,
+            jobs: None,
+            exclude: vec!["*.swp".to_string()],
+            last_modified: false,
         };
         let playpen_should_be = Playpen {
             editable: true,
             copy_js: true,
         };
+        let mut redirect_should_be = BTreeMap::new();
+        redirect_should_be.insert(
+            String::from("/old/chapter.html"),
+            String::from("/new/chapter.html"),
+        );
+        let mut color_schemes_should_be = BTreeMap::new();
+        color_schemes_should_be.insert(
+            String::from("solarized"),
+            ColorScheme {
+                name: Some(String::from("Solarized")),
+                css: PathBuf::from("theme/solarized.css"),
+                dark: true,
+            },
+        );
         let html_should_be = HtmlConfig {
             curly_quotes: true,
             google_analytics: Some(String::from("123456")),
             additional_css: vec![PathBuf::from("./foo/bar/baz.css")],
+            copy_exclude: vec!["fixtures/".to_string()],
             theme: Some(PathBuf::from("./themedir")),
+            parent_theme: Some(PathBuf::from("../shared-theme")),
             playpen: playpen_should_be,
+            redirect: redirect_should_be,
+            highlight: Highlight::Server,
+            default_theme: Some(String::from("rust")),
+            preferred_dark_theme: Some(String::from("solarized")),
+            color_schemes: color_schemes_should_be,
+            math: Math {
+                mode: MathMode::Vendored,
+                mathjax_dir: Some(PathBuf::from("./mathjax")),
+                ..Default::default()
+            },
+            fold: Fold {
+                enable: true,
+                level: 1,
+            },
+            images: Images {
+                dimensions: true,
+                unreferenced: Unreferenced::Warn,
+                deny_missing: false,
+                ..Default::default()
+            },
             ..Default::default()
         };
 
@@ -685,6 +967,10 @@ mod tests {
             build_dir: PathBuf::from("my-book"),
             create_missing: true,
             preprocess: None,
+            incremental: false,
+            jobs: None,
+            exclude: Vec::new(),
+            last_modified: true,
         };
 
         let html_should_be = HtmlConfig {
@@ -82,9 +82,14 @@
 
 #![deny(missing_docs)]
 
+extern crate base64;
+extern crate brotli;
 #[macro_use]
 extern crate error_chain;
+extern crate flate2;
+extern crate globset;
 extern crate handlebars;
+extern crate ignore;
 extern crate itertools;
 #[macro_use]
 extern crate lazy_static;
@@ -92,6 +97,7 @@ extern crate lazy_static;
 extern crate log;
 extern crate memchr;
 extern crate pulldown_cmark;
+extern crate rayon;
 extern crate regex;
 extern crate serde;
 #[macro_use]
@@ -103,6 +109,9 @@ extern crate tempfile;
 extern crate toml;
 extern crate toml_query;
 
+#[cfg(feature = "highlight")]
+extern crate syntect;
+
 #[cfg(test)]
 #[macro_use]
 extern crate pretty_assertions;
@@ -0,0 +1,94 @@
+//! Giving stylesheets and scripts names which change along with their contents
+//! (e.g. `book.3f2a9c81.css`), for `output.html.hash-files = true`.
+//!
+//! Browsers and CDNs can then cache them forever, because a new version of a
+//! file is always a new URL. Pages find the right names through the `asset`
+//! handlebars helper, and `manifest.json` lists them for everything else.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde_json;
+
+use errors::*;
+use renderer::html_handlebars::cache;
+use utils;
+
+/// The file listing what each asset was renamed to.
+pub const MANIFEST: &str = "manifest.json";
+
+/// The names every asset should be written as.
+#[derive(Debug, Default)]
+pub struct Assets {
+    names: BTreeMap<String, String>,
+}
+
+impl Assets {
+    /// Add an asset (e.g. `book.css`), giving it a name based on its contents.
+    pub fn add(&mut self, name: &str, content: &[u8]) {
+        self.names.insert(name.to_string(), hashed_name(name, content));
+    }
+
+    /// The name `name` should be written as, which is the same as the
+    /// original when it isn't being hashed.
+    pub fn name<'a>(&'a self, name: &'a str) -> &'a str {
+        self.names.get(name).map_or(name, String::as_str)
+    }
+
+    /// Like `name()`, but for a path (e.g. one from `additional-css`).
+    pub fn path(&self, path: &Path) -> PathBuf {
+        let name = utils::fs::normalize_path(&path.to_string_lossy());
+        PathBuf::from(self.name(&name))
+    }
+
+    /// Every asset's original name along with what it was renamed to, for the
+    /// templates.
+    pub fn to_json(&self) -> serde_json::Value {
+        json!(self.names)
+    }
+
+    /// The contents of `manifest.json`.
+    pub fn manifest(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(&self.names)?)
+    }
+}
+
+/// Put a hash of a file's contents in front of its extension, e.g.
+/// `FontAwesome/css/font-awesome.1c2d3e4f.css`.
+fn hashed_name(name: &str, content: &[u8]) -> String {
+    let hash = format!("{:016x}", cache::hash(content));
+    let hash = &hash[..8];
+
+    let file_name_start = name.rfind('/').map_or(0, |i| i + 1);
+    match name[file_name_start..].find('.') {
+        Some(dot) => {
+            let dot = file_name_start + dot;
+            format!("{}.{}{}", &name[..dot], hash, &name[dot..])
+        }
+        None => format!("{}.{}", name, hash),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hashes_go_in_front_of_the_extension() {
+        let mut assets = Assets::default();
+        assets.add("book.css", b"body {}");
+        assets.add("FontAwesome/css/font-awesome.css", b"");
+        assets.add("clipboard.min.js", b"");
+        assets.add("LICENSE", b"");
+
+        let hash = |content: &[u8]| format!("{:016x}", cache::hash(content))[..8].to_string();
+        assert_eq!(assets.name("book.css"), format!("book.{}.css", hash(b"body {}")));
+        assert_eq!(
+            assets.name("FontAwesome/css/font-awesome.css"),
+            format!("FontAwesome/css/font-awesome.{}.css", hash(b""))
+        );
+        assert_eq!(assets.name("clipboard.min.js"), format!("clipboard.{}.min.js", hash(b"")));
+        assert_eq!(assets.name("LICENSE"), format!("LICENSE.{}", hash(b"")));
+        assert_eq!(assets.name("unknown.js"), "unknown.js");
+    }
+}
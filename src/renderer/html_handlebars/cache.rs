@@ -0,0 +1,234 @@
+//! A small build cache which lets the HTML renderer skip work when running
+//! incrementally (i.e. `build.incremental = true`).
+//!
+//! Every file the renderer produces is recorded alongside a "key", a hash of
+//! whatever went into making it. On the next build, if the key for a file is
+//! unchanged and the file is still on disk, the renderer can skip regenerating
+//! it. Files which were produced last time but not this time are removed.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+
+use serde_json;
+
+use errors::*;
+use utils;
+
+/// The name of the manifest file kept in the destination directory.
+const CACHE_FILE: &str = ".mdbook-cache";
+
+/// Hash an arbitrary value into a cache key.
+pub fn hash<T: Hash + ?Sized>(value: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[derive(Debug, Default)]
+pub struct BuildCache {
+    enabled: bool,
+    destination: PathBuf,
+    /// The keys recorded by the previous build.
+    previous: BTreeMap<String, u64>,
+    /// The keys of every file produced by this build.
+    current: BTreeMap<String, u64>,
+}
+
+impl BuildCache {
+    /// Create a cache which never considers anything fresh and doesn't leave
+    /// a manifest behind.
+    pub fn disabled() -> BuildCache {
+        BuildCache::default()
+    }
+
+    /// Load the manifest left behind by a previous build in `destination`,
+    /// falling back to an empty cache if there isn't one (or it is unreadable).
+    pub fn load(destination: &Path) -> BuildCache {
+        let manifest = destination.join(CACHE_FILE);
+
+        let previous = if manifest.exists() {
+            utils::fs::file_to_string(&manifest)
+                .ok()
+                .and_then(|s| serde_json::from_str(&s).ok())
+                .unwrap_or_else(|| {
+                    warn!("Ignoring unreadable build cache, {}", manifest.display());
+                    BTreeMap::new()
+                })
+        } else {
+            BTreeMap::new()
+        };
+
+        BuildCache {
+            enabled: true,
+            destination: destination.to_path_buf(),
+            previous,
+            current: BTreeMap::new(),
+        }
+    }
+
+    /// Record that `path` will be produced from inputs with the given `key`,
+    /// returning `true` if the copy left over from the last build is still up
+    /// to date.
+    pub fn is_fresh<P: AsRef<Path>>(&mut self, path: P, key: u64) -> bool {
+        let path = path.as_ref();
+        let name = utils::fs::normalize_path(&path.to_string_lossy());
+
+        let fresh = self.enabled && self.previous.get(&name) == Some(&key)
+            && self.destination.join(path).exists();
+        self.current.insert(name, key);
+
+        fresh
+    }
+
+    /// Write a file to the destination directory, skipping it if its contents
+    /// are identical to what the previous build wrote.
+    pub fn write_file<P: AsRef<Path>>(
+        &mut self,
+        destination: &Path,
+        filename: P,
+        content: &[u8],
+    ) -> Result<()> {
+        let filename = filename.as_ref();
+
+        if self.is_fresh(filename, hash(content)) {
+            trace!("Skipping unchanged {}", filename.display());
+            Ok(())
+        } else {
+            utils::fs::write_file(destination, filename, content)
+        }
+    }
+
+    /// Copy `from` to the destination directory, skipping it if the source
+    /// file has the same size and modification time as on the previous build.
+    pub fn copy_file<P: AsRef<Path>>(
+        &mut self,
+        from: &Path,
+        destination: &Path,
+        filename: P,
+    ) -> Result<()> {
+        let filename = filename.as_ref();
+        let metadata =
+            fs::metadata(from).chain_err(|| format!("Unable to read {}", from.display()))?;
+
+        if self.is_fresh(filename, hash(&(metadata.len(), metadata.modified().ok()))) {
+            trace!("Skipping unchanged {}", filename.display());
+            return Ok(());
+        }
+
+        let to = destination.join(filename);
+        debug!("Copying {} to {}", from.display(), to.display());
+        if let Some(parent) = to.parent() {
+            fs::create_dir_all(parent)
+                .chain_err(|| format!("Unable to create {}", parent.display()))?;
+        }
+        fs::copy(from, &to)
+            .chain_err(|| format!("Unable to copy {} to {}", from.display(), to.display()))?;
+
+        Ok(())
+    }
+
+    /// Was `name` produced by the previous build but (so far) not by this
+    /// one, meaning `finish()` will remove it?
+    pub fn is_stale(&self, name: &str) -> bool {
+        self.enabled && self.previous.contains_key(name) && !self.current.contains_key(name)
+    }
+
+    /// Remove anything the previous build produced which wasn't produced this
+    /// time around, then save the manifest for next time.
+    pub fn finish(self) -> Result<()> {
+        if !self.enabled {
+            return Ok(());
+        }
+
+        let produced: BTreeSet<_> = self.current.keys().collect();
+
+        for stale in self.previous.keys().filter(|name| !produced.contains(name)) {
+            let path = self.destination.join(stale);
+            if path.is_file() {
+                debug!("Removing stale output {}", path.display());
+                fs::remove_file(&path)
+                    .chain_err(|| format!("Unable to remove {}", path.display()))?;
+            }
+        }
+
+        let manifest = serde_json::to_string(&self.current)?;
+        utils::fs::write_file(&self.destination, CACHE_FILE, manifest.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::Builder as TempFileBuilder;
+
+    #[test]
+    fn disabled_cache_is_never_fresh() {
+        let temp = TempFileBuilder::new().prefix("mdbook").tempdir().unwrap();
+        let mut cache = BuildCache::disabled();
+
+        cache.write_file(temp.path(), "foo.html", b"foo").unwrap();
+        assert!(!cache.is_fresh("foo.html", hash(&b"foo"[..])));
+
+        cache.finish().unwrap();
+        assert!(!temp.path().join(CACHE_FILE).exists());
+    }
+
+    #[test]
+    fn unchanged_files_are_fresh_on_the_next_build() {
+        let temp = TempFileBuilder::new().prefix("mdbook").tempdir().unwrap();
+
+        let mut cache = BuildCache::load(temp.path());
+        assert!(!cache.is_fresh("chapter.html", 1));
+        utils::fs::write_file(temp.path(), "chapter.html", b"chapter").unwrap();
+        cache.write_file(temp.path(), "book.css", b"css").unwrap();
+        cache.finish().unwrap();
+
+        let mut cache = BuildCache::load(temp.path());
+        assert!(cache.is_fresh("chapter.html", 1));
+        assert!(!cache.is_fresh("book.css", hash(&b"changed"[..])));
+    }
+
+    #[test]
+    fn unchanged_copies_are_skipped_and_removed_copies_cleaned_up() {
+        let temp = TempFileBuilder::new().prefix("mdbook").tempdir().unwrap();
+        let src = temp.path().join("src");
+        let dest = temp.path().join("book");
+        utils::fs::write_file(&src, "image.png", b"image").unwrap();
+
+        let mut cache = BuildCache::load(&dest);
+        cache.copy_file(&src.join("image.png"), &dest, "image.png").unwrap();
+        cache.finish().unwrap();
+
+        // Tamper with the output, an unchanged source shouldn't be copied again
+        utils::fs::write_file(&dest, "image.png", b"output").unwrap();
+        let mut cache = BuildCache::load(&dest);
+        cache.copy_file(&src.join("image.png"), &dest, "image.png").unwrap();
+        cache.finish().unwrap();
+        assert_eq!(utils::fs::file_to_string(dest.join("image.png")).unwrap(), "output");
+
+        // Nothing copies it this time, so it's stale
+        let cache = BuildCache::load(&dest);
+        cache.finish().unwrap();
+        assert!(!dest.join("image.png").exists());
+    }
+
+    #[test]
+    fn outputs_which_are_no_longer_produced_get_removed() {
+        let temp = TempFileBuilder::new().prefix("mdbook").tempdir().unwrap();
+
+        let mut cache = BuildCache::load(temp.path());
+        cache.write_file(temp.path(), "old/chapter.html", b"old").unwrap();
+        cache.write_file(temp.path(), "new.html", b"new").unwrap();
+        cache.finish().unwrap();
+
+        let mut cache = BuildCache::load(temp.path());
+        cache.write_file(temp.path(), "new.html", b"new").unwrap();
+        cache.finish().unwrap();
+
+        assert!(!temp.path().join("old/chapter.html").exists());
+        assert!(temp.path().join("new.html").exists());
+    }
+}
@@ -0,0 +1,210 @@
+//! The color themes readers can pick from the theme menu: the built-in ones
+//! and those declared in `[output.html.color-schemes]`.
+
+use serde_json::Value;
+
+use config::HtmlConfig;
+use errors::*;
+
+/// The built-in color themes, as `(id, name, code highlighting)`. Their
+/// colors are in `book.css`.
+const BUILTIN: &[(&str, &str, &str)] = &[
+    ("light", "Light", "highlight"),
+    ("rust", "Rust", "highlight"),
+    ("coal", "Coal", "tomorrow-night"),
+    ("navy", "Navy", "tomorrow-night"),
+    ("ayu", "Ayu", "ayu"),
+];
+
+/// The ids and classes the default theme already uses on the page. A
+/// scheme's id ends up as a class on `<html>` and `<body>` and as the id of
+/// its button in the theme menu, so it can't be any of these.
+const RESERVED: &[&str] = &[
+    "active", "affix", "ayu-highlight-css", "bordered", "buttons", "chapter", "clip-button",
+    "collapsed", "content", "default", "edit-button", "editable", "expanded", "fa", "folded",
+    "footnote-definition", "header", "hidden", "highlight-css", "hljs", "icon-button", "js",
+    "last-modified", "left-buttons", "menu-bar", "menu-bar-sticky-container", "menu-title",
+    "mobile-nav-chapters", "nav-chapters", "nav-wide-wrapper", "nav-wrapper", "next", "no-js",
+    "page", "page-wrapper", "play-button", "playpen", "previous", "print-button", "reset-button",
+    "result", "right-buttons", "search-toggle", "search-wrapper", "searchbar", "searchbar-outer",
+    "searchresults", "searchresults-header", "searchresults-outer", "section", "sidebar",
+    "sidebar-hidden", "sidebar-toggle", "sidebar-visible", "spacer", "theme", "theme-list",
+    "theme-popup", "theme-toggle", "toggle", "tomorrow-night-css", "tooltipped", "tooltiptext",
+    "unhidden",
+];
+
+const DEFAULT_THEME: &str = "light";
+const PREFERRED_DARK_THEME: &str = "navy";
+
+/// The color themes, in the order they are listed in the theme menu.
+#[derive(Debug)]
+pub struct ColorSchemes {
+    schemes: Vec<(String, String, &'static str)>,
+    default_theme: String,
+    preferred_dark_theme: String,
+}
+
+impl ColorSchemes {
+    pub fn new(html_config: &HtmlConfig) -> Result<ColorSchemes> {
+        let mut schemes = BUILTIN
+            .iter()
+            .map(|&(id, name, highlight)| (id.to_string(), name.to_string(), highlight))
+            .collect::<Vec<_>>();
+
+        for (id, scheme) in &html_config.color_schemes {
+            if !is_valid_id(id) {
+                bail!(
+                    "\"{}\" can't be used as the id of a color scheme, it should start with a \
+                     letter and only contain letters, digits, `-` and `_`",
+                    id
+                );
+            }
+            if BUILTIN.iter().any(|&(builtin, _, _)| builtin == id) {
+                bail!("The \"{}\" color scheme is already built into mdBook", id);
+            }
+            if RESERVED.contains(&id.as_str()) {
+                bail!(
+                    "\"{}\" can't be used as the id of a color scheme, the page already uses \
+                     it as an id or class",
+                    id
+                );
+            }
+            if scheme.css.as_os_str().is_empty() {
+                bail!("The \"{}\" color scheme needs a `css` file", id);
+            }
+
+            let name = scheme.name.clone().unwrap_or_else(|| id.clone());
+            let highlight = if scheme.dark {
+                "tomorrow-night"
+            } else {
+                "highlight"
+            };
+            schemes.push((id.clone(), name, highlight));
+        }
+
+        let known = |key: &str, id: Option<&String>, default: &str| -> Result<String> {
+            let id = id.map_or(default, String::as_str);
+            if !schemes.iter().any(|scheme| scheme.0 == id) {
+                bail!("`output.html.{}` is \"{}\", which isn't a color scheme", key, id);
+            }
+            Ok(id.to_string())
+        };
+        let default_theme = known(
+            "default-theme",
+            html_config.default_theme.as_ref(),
+            DEFAULT_THEME,
+        )?;
+        let preferred_dark_theme = known(
+            "preferred-dark-theme",
+            html_config.preferred_dark_theme.as_ref(),
+            PREFERRED_DARK_THEME,
+        )?;
+
+        Ok(ColorSchemes {
+            schemes,
+            default_theme,
+            preferred_dark_theme,
+        })
+    }
+
+    /// Add the `color_schemes`, `default_theme` and `preferred_dark_theme`
+    /// template data.
+    pub fn insert_data(&self, data: &mut ::serde_json::Map<String, Value>) {
+        let schemes = self.schemes
+            .iter()
+            .map(|&(ref id, ref name, highlight)| {
+                json!({
+                    "id": id,
+                    "name": name,
+                    "highlight": highlight,
+                    "default": *id == self.default_theme,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        data.insert("color_schemes".to_owned(), json!(schemes));
+        data.insert("default_theme".to_owned(), json!(self.default_theme));
+        data.insert(
+            "preferred_dark_theme".to_owned(),
+            json!(self.preferred_dark_theme),
+        );
+    }
+}
+
+/// Ids end up as CSS classes and element ids, so they are kept simple.
+fn is_valid_id(id: &str) -> bool {
+    id.starts_with(|c: char| c.is_ascii_alphabetic())
+        && id.chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::ColorScheme;
+    use serde_json::Map;
+    use std::path::PathBuf;
+
+    fn solarized(dark: bool) -> ColorScheme {
+        ColorScheme {
+            name: Some("Solarized".to_string()),
+            css: PathBuf::from("theme/solarized.css"),
+            dark,
+        }
+    }
+
+    #[test]
+    fn custom_schemes_follow_the_built_in_ones() {
+        let mut html_config = HtmlConfig::default();
+        html_config
+            .color_schemes
+            .insert("solarized".to_string(), solarized(true));
+        html_config.preferred_dark_theme = Some("solarized".to_string());
+
+        let mut data = Map::new();
+        ColorSchemes::new(&html_config)
+            .unwrap()
+            .insert_data(&mut data);
+
+        let ids = data["color_schemes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|scheme| scheme["id"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec!["light", "rust", "coal", "navy", "ayu", "solarized"]);
+        assert_eq!(
+            data["color_schemes"][5],
+            json!({
+                "id": "solarized",
+                "name": "Solarized",
+                "highlight": "tomorrow-night",
+                "default": false,
+            })
+        );
+        assert_eq!(data["color_schemes"][0]["default"], json!(true));
+        assert_eq!(data["default_theme"], json!("light"));
+        assert_eq!(data["preferred_dark_theme"], json!("solarized"));
+    }
+
+    #[test]
+    fn schemes_must_be_usable() {
+        let check = |id: &str, scheme: ColorScheme, default_theme: &str| {
+            let mut html_config = HtmlConfig::default();
+            html_config.color_schemes.insert(id.to_string(), scheme);
+            html_config.default_theme = Some(default_theme.to_string());
+            ColorSchemes::new(&html_config).is_ok()
+        };
+
+        assert!(check("solarized", solarized(false), "solarized"));
+        assert!(check("solarized", solarized(false), "ayu"));
+        assert!(!check("solarized", solarized(false), "missing"));
+        assert!(!check("navy", solarized(false), "light"));
+        assert!(!check("sidebar", solarized(false), "light"));
+        assert!(!check("content", solarized(false), "light"));
+        assert!(!check("js", solarized(false), "light"));
+        assert!(!check("solar ized", solarized(false), "light"));
+        assert!(!check("1st", solarized(false), "light"));
+        assert!(!check("solarized", ColorScheme::default(), "light"));
+    }
+}
@@ -1,132 +1,152 @@
 use book::{Book, BookItem, Chapter};
-use config::{Config, HtmlConfig, Playpen};
+use config::{Config, Highlight, HtmlConfig, MathMode, Playpen, Unreferenced};
 use errors::*;
+use renderer::html_handlebars::assets::{self, Assets};
+use renderer::html_handlebars::cache::{self, BuildCache};
+use renderer::html_handlebars::color_schemes::ColorSchemes;
 use renderer::html_handlebars::helpers;
+use renderer::html_handlebars::images::ImageChecker;
+use renderer::html_handlebars::links::LinkChecker;
+use renderer::html_handlebars::math::MathRenderer;
+use renderer::html_handlebars::minify;
+use renderer::html_handlebars::offline;
+use renderer::html_handlebars::single_file;
+use renderer::html_handlebars::templates::{self, PageTemplates};
+use renderer::html_handlebars::tree;
 use renderer::{RenderContext, Renderer};
-use theme::{self, playpen_editor, Theme};
+use theme::{self, fonts, playpen_editor, Theme, ThemeLayer};
 use utils;
+use utils::fs::ExcludedFiles;
+use utils::git::LastModified;
 
 use std::collections::BTreeMap;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs::{self, File};
 use std::io::Read;
-use std::path::{Path, PathBuf};
-
-use handlebars::Handlebars;
-use regex::{Captures, Regex};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+use handlebars::{Handlebars, Helper, HelperDef, HelperResult, RenderContext as HbsRenderContext,
+                 RenderError};
+use rayon::prelude::*;
+use rayon::ThreadPoolBuilder;
+use serde::ser::{Serialize, SerializeMap, Serializer};
 use serde_json;
 
 #[derive(Default)]
-pub struct HtmlHandlebars;
+pub struct HtmlHandlebars {
+    helpers: Vec<(String, Arc<dyn HelperDef>)>,
+}
 
 impl HtmlHandlebars {
     pub fn new() -> Self {
-        HtmlHandlebars
+        HtmlHandlebars::default()
     }
 
-    fn render_item(
-        &self,
-        item: &BookItem,
-        mut ctx: RenderItemContext,
-        print_content: &mut String,
-    ) -> Result<()> {
-        // FIXME: This should be made DRY-er and rely less on mutable state
-        match *item {
-            BookItem::Chapter(ref ch) => {
-                let content = ch.content.clone();
-                let content = utils::render_markdown(&content, ctx.html_config.curly_quotes);
-                print_content.push_str(&content);
-
-                // Update the context with data for this file
-                let path = ch.path
-                    .to_str()
-                    .chain_err(|| "Could not convert path to str")?;
-                let filepath = Path::new(&ch.path).with_extension("html");
-                let filepathstr = filepath
-                    .to_str()
-                    .chain_err(|| "Could not convert HTML path to str")?;
-                let filepathstr = utils::fs::normalize_path(filepathstr);
-
-                // "print.html" is used for the print page.
-                if ch.path == Path::new("print.md") {
-                    bail!(ErrorKind::ReservedFilenameError(ch.path.clone()));
-                };
-
-                // Non-lexical lifetimes needed :'(
-                let title: String;
-                {
-                    let book_title = ctx.data
-                        .get("book_title")
-                        .and_then(serde_json::Value::as_str)
-                        .unwrap_or("");
-                    title = ch.name.clone() + " - " + book_title;
-                }
-
-                ctx.data.insert("path".to_owned(), json!(path));
-                ctx.data.insert("content".to_owned(), json!(content));
-                ctx.data.insert("chapter_title".to_owned(), json!(ch.name));
-                ctx.data.insert("title".to_owned(), json!(title));
-                ctx.data.insert(
-                    "path_to_root".to_owned(),
-                    json!(utils::fs::path_to_root(&ch.path)),
-                );
-
-                // Render the handlebars template with the data
-                debug!("Render template");
-                let rendered = ctx.handlebars.render("index", &ctx.data)?;
-
-                let rendered = self.post_process(rendered, &filepathstr, &ctx.html_config.playpen);
-
-                // Write to file
-                debug!("Creating {} ✓", filepathstr);
-                utils::fs::write_file(&ctx.destination, &filepath, &rendered.into_bytes())?;
-
-                if ctx.is_index {
-                    self.render_index(ch, &ctx.destination)?;
-                }
-            }
-            _ => {}
-        }
-
-        Ok(())
+    /// Make an extra handlebars helper available to the theme's templates,
+    /// so crates building books with their own themes don't need to fork
+    /// `index.hbs` to get at data mdBook doesn't expose. A helper with the
+    /// same name as a built-in one (e.g. `toc`) replaces it.
+    ///
+    /// Pass the renderer to `MDBook::with_renderer()` to use it.
+    pub fn register_helper<H: HelperDef + 'static>(&mut self, name: &str, helper: H) -> &mut Self {
+        self.helpers.push((name.to_string(), Arc::new(helper)));
+        self
     }
 
-    /// Create an index.html from the first element in SUMMARY.md
-    fn render_index(&self, ch: &Chapter, destination: &Path) -> Result<()> {
-        debug!("index.html");
-
-        let mut content = String::new();
+    fn render_item(&self, ch: &Chapter, ctx: RenderItemContext) -> Result<()> {
+        // FIXME: This should be made DRY-er and rely less on mutable state
 
-        File::open(destination.join(&ch.path.with_extension("html")))?
-            .read_to_string(&mut content)?;
+        // Update the context with data for this file
+        let path = ch.path
+            .to_str()
+            .chain_err(|| "Could not convert path to str")?;
+        let filepath = Path::new(&ch.path).with_extension("html");
+        let filepathstr = filepath
+            .to_str()
+            .chain_err(|| "Could not convert HTML path to str")?;
+        let filepathstr = utils::fs::normalize_path(filepathstr);
+
+        let options = utils::RenderOptions {
+            curly_quotes: ctx.html_config.curly_quotes,
+            path: Some(&filepathstr),
+            playpen: playpen(ctx.html_config),
+            highlight: ctx.html_config.highlight == Highlight::Server,
+            ..Default::default()
+        };
+        let mut content = utils::render_markdown_with_options(&ch.content, &options, &mut HashMap::new());
+        if let Some(math) = ctx.math {
+            content = math.render(&content)
+                .chain_err(|| format!("Unable to render the math in {}", ch.path.display()))?;
+        }
+        let content = ctx.images
+            .add_attributes(&content, &ch.path, None, &ctx.html_config.images);
 
-        // This could cause a problem when someone displays
-        // code containing <base href=...>
-        // on the front page, however this case should be very very rare...
-        content = content
-            .lines()
-            .filter(|line| !line.contains("<base href="))
-            .collect::<Vec<&str>>()
-            .join("\n");
+        // "print.html" is used for the print page.
+        if ch.path == Path::new("print.md") {
+            bail!(ErrorKind::ReservedFilenameError(ch.path.clone()));
+        };
 
-        utils::fs::write_file(destination, "index.html", content.as_bytes())?;
+        // Non-lexical lifetimes needed :'(
+        let title: String;
+        {
+            let book_title = ctx.data
+                .get("book_title")
+                .and_then(serde_json::Value::as_str)
+                .unwrap_or("");
+            title = ch.name.clone() + " - " + book_title;
+        }
 
-        debug!(
-            "Creating index.html from {} ✓",
-            destination.join(&ch.path.with_extension("html")).display()
+        // Only the keys which differ between pages are set here, the rest is
+        // shared by every chapter
+        let mut page = serde_json::Map::new();
+        let chapter = ctx.data
+            .get("chapter_tree")
+            .and_then(serde_json::Value::as_array)
+            .and_then(|tree| tree::lineage(tree, &utils::fs::normalize_path(path)).pop().cloned());
+        if let Some(chapter) = chapter {
+            page.insert("chapter".to_owned(), chapter);
+        }
+        page.insert("path".to_owned(), json!(path));
+        page.insert("content".to_owned(), json!(content));
+        page.insert("chapter_title".to_owned(), json!(ch.name));
+        page.insert("title".to_owned(), json!(title));
+        page.insert(
+            "path_to_root".to_owned(),
+            json!(utils::fs::path_to_root(&ch.path)),
         );
+        if let Some(url) = canonical_url(ctx.data, &filepathstr) {
+            page.insert("canonical_url".to_owned(), json!(url));
+        }
+        if let Some(ref edit_url) = ctx.edit_url {
+            page.insert("edit_url".to_owned(), json!(edit_url));
+        }
+        if let Some(last_modified) = ctx.last_modified {
+            page.insert("last_modified".to_owned(), json!(last_modified));
+        }
 
-        Ok(())
-    }
-
-    #[cfg_attr(feature = "cargo-clippy", allow(let_and_return))]
-    fn post_process(&self, rendered: String, filepath: &str, playpen_config: &Playpen) -> String {
-        let rendered = build_header_links(&rendered, filepath);
-        let rendered = fix_anchor_links(&rendered, filepath);
-        let rendered = fix_code_blocks(&rendered);
-        let rendered = add_playpen_pre(&rendered, playpen_config);
+        // Render the handlebars template with the data
+        debug!("Render template");
+        let mut data = PageData {
+            global: ctx.data,
+            page,
+        };
+        let rendered = render_page(ctx.handlebars, &ctx.template, &data, ctx.html_config)?;
+
+        // Write to file
+        debug!("Creating {} ✓", filepathstr);
+        utils::fs::write_file(ctx.destination, &filepath, &rendered.into_bytes())?;
+
+        if ctx.is_index {
+            // The first chapter doubles as the front page, which lives at the
+            // root of the book
+            data.page.insert("path_to_root".to_owned(), json!(""));
+            let rendered_index = render_page(ctx.handlebars, &ctx.template, &data, ctx.html_config)?;
+            debug!("Creating index.html from {} ✓", filepathstr);
+            utils::fs::write_file(ctx.destination, "index.html", rendered_index.as_bytes())?;
+        }
 
-        rendered
+        Ok(())
     }
 
     fn copy_static_files(
@@ -134,25 +154,28 @@ impl HtmlHandlebars {
         destination: &Path,
         theme: &Theme,
         html_config: &HtmlConfig,
+        assets: &Assets,
+        cache: &mut BuildCache,
     ) -> Result<()> {
-        use utils::fs::write_file;
+        let mut write_file = |destination: &Path, filename: &str, content: &[u8]| {
+            cache.write_file(destination, filename, content)
+        };
 
         write_file(destination, ".nojekyll", 
             b"This file makes sure that Github Pages doesn't process mdBook's output.")?;
 
-        write_file(destination, "book.js", &theme.js)?;
-        write_file(destination, "book.css", &theme.css)?;
-        write_file(destination, "favicon.png", &theme.favicon)?;
-        write_file(destination, "highlight.css", &theme.highlight_css)?;
-        write_file(destination, "tomorrow-night.css", &theme.tomorrow_night_css)?;
-        write_file(destination, "ayu-highlight.css", &theme.ayu_highlight_css)?;
-        write_file(destination, "highlight.js", &theme.highlight_js)?;
-        write_file(destination, "clipboard.min.js", &theme.clipboard_js)?;
-        write_file(
-            destination,
-            "FontAwesome/css/font-awesome.css",
-            theme::FONT_AWESOME,
-        )?;
+        for (filename, content) in linked_files(theme, html_config) {
+            if html_config.minify {
+                write_file(destination, assets.name(filename), &minify::file(filename, content))?;
+            } else {
+                write_file(destination, assets.name(filename), content)?;
+            }
+        }
+        if html_config.offline || html_config.single_file {
+            for &(filename, content) in fonts::FILES {
+                write_file(destination, filename, content)?;
+            }
+        }
         write_file(
             destination,
             "FontAwesome/fonts/fontawesome-webfont.eot",
@@ -184,22 +207,6 @@ impl HtmlHandlebars {
             theme::FONT_AWESOME_TTF,
         )?;
 
-        let playpen_config = &html_config.playpen;
-
-        // Ace is a very large dependency, so only load it when requested
-        if playpen_config.editable && playpen_config.copy_js {
-            // Load the editor
-            write_file(destination, "editor.js", playpen_editor::JS)?;
-            write_file(destination, "ace.js", playpen_editor::ACE_JS)?;
-            write_file(destination, "mode-rust.js", playpen_editor::MODE_RUST_JS)?;
-            write_file(destination, "theme-dawn.js", playpen_editor::THEME_DAWN_JS)?;
-            write_file(
-                destination,
-                "theme-tomorrow_night.js",
-                playpen_editor::THEME_TOMORROW_NIGHT_JS,
-            )?;
-        }
-
         Ok(())
     }
 
@@ -219,6 +226,120 @@ impl HtmlHandlebars {
             "path_to_root".to_owned(),
             json!(utils::fs::path_to_root(Path::new("print.md"))),
         );
+        if let Some(url) = canonical_url(data, "print.html") {
+            data.insert("canonical_url".to_owned(), json!(url));
+        }
+    }
+
+    /// Write a small stub page at each redirect source which sends readers
+    /// on to the new location.
+    fn emit_redirects(
+        &self,
+        destination: &Path,
+        handlebars: &Handlebars,
+        data: &serde_json::Map<String, serde_json::Value>,
+        redirects: &BTreeMap<String, String>,
+        outputs: &HashSet<String>,
+        cache: &mut BuildCache,
+    ) -> Result<()> {
+        for (original, new) in redirects {
+            let source = redirect_source(original)?;
+            if outputs.contains(&source) {
+                bail!(
+                    "The redirect source \"{}\" would overwrite a rendered page",
+                    original
+                );
+            }
+
+            let mut redirect_data = BTreeMap::new();
+            redirect_data.insert("language", data["language"].clone());
+            redirect_data.insert("url", json!(redirect_target(&source, new)));
+
+            debug!("Redirecting \"{}\" to \"{}\"", source, new);
+            let rendered = handlebars.render("redirect", &redirect_data)?;
+            cache.write_file(destination, &source, rendered.as_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    /// Render every chapter onto a single page, then embed everything it needs
+    /// so it can be read without the rest of the book.
+    fn render_single_file(&self, ctx: SingleFileContext, cache: &mut BuildCache) -> Result<()> {
+        let mut data = ctx.data.clone();
+        // There's nowhere else to go, so no print button either
+        data.remove("print_enable");
+        data.insert("bundled_fonts".to_owned(), json!(true));
+        data.insert("path".to_owned(), json!("book.md"));
+        data.insert("content".to_owned(), json!(ctx.content));
+        data.insert("path_to_root".to_owned(), json!(""));
+        data.insert(
+            "page_anchors".to_owned(),
+            json!(serde_json::to_string(ctx.page_anchors)?),
+        );
+        if let Some(title) = data.get("book_title").cloned() {
+            data.insert("title".to_owned(), title);
+        }
+
+        debug!("Render {}", single_file::FILE_NAME);
+        let rendered = render_page(ctx.handlebars, templates::DEFAULT, &data, ctx.html_config)?;
+        let rendered = single_file::inline(&rendered, ctx.destination, ctx.page_anchors)?;
+        cache.write_file(ctx.destination, single_file::FILE_NAME, rendered.as_bytes())
+    }
+
+    /// Render a "page not found" page. Web servers can serve it from any
+    /// URL, so its links are made relative to the site URL (or the root of
+    /// the server) rather than to where the page happens to be.
+    fn render_404(
+        &self,
+        handlebars: &Handlebars,
+        data: &serde_json::Map<String, serde_json::Value>,
+        html_config: &HtmlConfig,
+        destination: &Path,
+        cache: &mut BuildCache,
+    ) -> Result<()> {
+        let mut data = data.clone();
+        let options = utils::RenderOptions {
+            curly_quotes: html_config.curly_quotes,
+            path: Some("404.html"),
+            playpen: playpen(html_config),
+            highlight: html_config.highlight == Highlight::Server,
+            ..Default::default()
+        };
+        let root = match data.get("site_url").and_then(serde_json::Value::as_str) {
+            Some(url) => url.to_string(),
+            None => {
+                warn!(
+                    "`output.html.site-url` isn't set, so the links on 404.html only work if \
+                     the book is served from the root of the site"
+                );
+                "/".to_string()
+            }
+        };
+        let content = utils::render_markdown_with_options(
+            &format!(
+                "# Document not found (404)\n\nThis URL is invalid, sorry. \
+                 Try going back to [the start of the book]({}index.html) instead.",
+                root
+            ),
+            &options,
+            &mut HashMap::new(),
+        );
+        let title = match data.get("book_title").and_then(serde_json::Value::as_str) {
+            Some(book_title) if !book_title.is_empty() => {
+                format!("Page not found - {}", book_title)
+            }
+            _ => "Page not found".to_string(),
+        };
+
+        data.insert("path".to_owned(), json!("404.md"));
+        data.insert("content".to_owned(), json!(content));
+        data.insert("title".to_owned(), json!(title));
+        data.insert("path_to_root".to_owned(), json!(root));
+
+        debug!("Render 404 page");
+        let rendered = render_page(handlebars, templates::DEFAULT, &data, html_config)?;
+        cache.write_file(destination, "404.html", rendered.as_bytes())
     }
 
     fn register_hbs_helpers(&self, handlebars: &mut Handlebars, html_config: &HtmlConfig) {
@@ -226,10 +347,53 @@ impl HtmlHandlebars {
             "toc",
             Box::new(helpers::toc::RenderToc {
                 no_section_label: html_config.no_section_label,
+                fold: html_config.fold,
             }),
         );
+        handlebars.register_helper("asset", Box::new(helpers::asset::asset));
         handlebars.register_helper("previous", Box::new(helpers::navigation::previous));
         handlebars.register_helper("next", Box::new(helpers::navigation::next));
+        handlebars.register_helper("breadcrumbs", Box::new(helpers::hierarchy::breadcrumbs));
+        handlebars.register_helper("children", Box::new(helpers::hierarchy::children));
+        handlebars.register_helper(
+            "previous_sibling",
+            Box::new(helpers::hierarchy::previous_sibling),
+        );
+        handlebars.register_helper("next_sibling", Box::new(helpers::hierarchy::next_sibling));
+
+        for (name, helper) in &self.helpers {
+            handlebars.register_helper(name, Box::new(SharedHelper(Arc::clone(helper))));
+        }
+    }
+
+    /// Copy the vendored copy of MathJax into `mathjax/`. It is made of
+    /// thousands of files, so the ones which haven't changed (going by their
+    /// size and modification time) are left alone.
+    fn copy_mathjax(
+        &self,
+        html: &HtmlConfig,
+        root: &Path,
+        destination: &Path,
+        cache: &mut BuildCache,
+    ) -> Result<()> {
+        let mathjax_dir = match html.math.mathjax_dir {
+            Some(ref dir) => root.join(dir),
+            None => bail!("Vendoring MathJax requires `output.html.math.mathjax-dir`"),
+        };
+        if !mathjax_dir.join("MathJax.js").is_file() {
+            bail!("{} doesn't contain MathJax.js", mathjax_dir.display());
+        }
+
+        debug!("Copying MathJax from {}", mathjax_dir.display());
+        for file in utils::fs::visible_files(&mathjax_dir)? {
+            cache.copy_file(
+                &mathjax_dir.join(&file),
+                destination,
+                format!("mathjax/{}", file),
+            )?;
+        }
+
+        Ok(())
     }
 
     /// Copy across any additional CSS and JavaScript files which the book
@@ -239,6 +403,8 @@ impl HtmlHandlebars {
         html: &HtmlConfig,
         root: &Path,
         destination: &Path,
+        assets: &Assets,
+        cache: &mut BuildCache,
     ) -> Result<()> {
         let custom_files = html.additional_css.iter().chain(html.additional_js.iter());
 
@@ -246,30 +412,70 @@ impl HtmlHandlebars {
 
         for custom_file in custom_files {
             let input_location = root.join(custom_file);
-            let output_location = destination.join(custom_file);
-            if let Some(parent) = output_location.parent() {
-                fs::create_dir_all(parent)
-                    .chain_err(|| format!("Unable to create {}", parent.display()))?;
-            }
+            let output_name = assets.path(custom_file);
             debug!(
                 "Copying {} -> {}",
                 input_location.display(),
-                output_location.display()
+                destination.join(&output_name).display()
             );
 
-            fs::copy(&input_location, &output_location).chain_err(|| {
-                format!(
-                    "Unable to copy {} to {}",
-                    input_location.display(),
-                    output_location.display()
-                )
-            })?;
+            let mut content = Vec::new();
+            File::open(&input_location)
+                .and_then(|mut f| f.read_to_end(&mut content))
+                .chain_err(|| format!("Unable to read {}", input_location.display()))?;
+            cache.write_file(destination, &output_name, &content)?;
         }
 
         Ok(())
     }
 }
 
+/// The layers the book's theme is put together from, lowest priority first:
+/// the `output.html.parent-theme` (if there is one), then the book's own
+/// theme. Either of them can name one of the built-in themes instead of a
+/// directory, in which case the book's own files still come from `theme/`.
+/// The default files are always at the bottom.
+fn theme_layers(root: &Path, html_config: &HtmlConfig) -> Result<Vec<ThemeLayer>> {
+    let mut layers = Vec::new();
+
+    if let Some(ref parent) = html_config.parent_theme {
+        let dir = root.join(parent);
+        if dir.is_dir() {
+            layers.push(ThemeLayer::Dir(dir));
+        } else {
+            match parent.to_str().and_then(theme::builtin_theme) {
+                Some(builtin) => layers.push(ThemeLayer::Builtin(builtin)),
+                None => bail!(
+                    "The parent theme directory {} doesn't exist, and \"{}\" isn't one of the \
+                     built-in themes ({})",
+                    dir.display(),
+                    parent.display(),
+                    theme::BUILTIN_THEMES
+                        .iter()
+                        .map(|theme| theme.name)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            }
+        }
+    }
+
+    let theme_dir = html_config.theme_dir(&root.to_path_buf());
+    let builtin = match html_config.theme {
+        Some(ref theme) if !theme_dir.is_dir() => theme.to_str().and_then(theme::builtin_theme),
+        _ => None,
+    };
+    match builtin {
+        Some(builtin) => {
+            layers.push(ThemeLayer::Builtin(builtin));
+            layers.push(ThemeLayer::Dir(root.join("theme")));
+        }
+        None => layers.push(ThemeLayer::Dir(theme_dir)),
+    }
+
+    Ok(layers)
+}
+
 // TODO(mattico): Remove some time after the 0.1.8 release
 fn maybe_wrong_theme_dir(dir: &Path) -> Result<bool> {
     fn entry_is_maybe_book_file(entry: fs::DirEntry) -> Result<bool> {
@@ -295,7 +501,43 @@ impl Renderer for HtmlHandlebars {
     }
 
     fn render(&self, ctx: &RenderContext) -> Result<()> {
-        let html_config = ctx.config.html_config().unwrap_or_default();
+        let mut html_config = ctx.config.html_config().unwrap_or_default();
+        if html_config.highlight == Highlight::Server && !utils::highlight::is_supported() {
+            warn!("mdBook compiled without server-side highlighting support, using highlight.js");
+            warn!(
+                "please reinstall with `cargo install mdbook --force --features highlight` to \
+                 highlight code while rendering"
+            );
+            html_config.highlight = Highlight::Client;
+        }
+        if html_config.offline && html_config.mathjax_support
+            && html_config.math.mode == MathMode::Cdn
+        {
+            warn!("MathJax is loaded from a CDN, which doesn't work offline");
+            warn!(
+                "Set `output.html.math.mode` to \"vendored\" or \"command\" to use math in an \
+                 offline book"
+            );
+        }
+        // MathJax loads its extensions from files next to MathJax.js, which
+        // can't be embedded in a single page
+        if html_config.single_file && html_config.mathjax_support
+            && html_config.math.mode == MathMode::Vendored
+        {
+            bail!(
+                "The single-file version of the book can't use a vendored copy of MathJax, set \
+                 `output.html.math.mode` to \"command\" to render the math while building"
+            );
+        }
+        // The custom color schemes' stylesheets are loaded on every page,
+        // just like the additional ones
+        let color_schemes = ColorSchemes::new(&html_config)?;
+        let scheme_css = html_config
+            .color_schemes
+            .values()
+            .map(|scheme| scheme.css.clone())
+            .collect::<Vec<_>>();
+        html_config.additional_css.extend(scheme_css);
         let src_dir = ctx.root.join(&ctx.config.book.src);
         let destination = &ctx.destination;
         let book = &ctx.book;
@@ -303,11 +545,6 @@ impl Renderer for HtmlHandlebars {
         trace!("render");
         let mut handlebars = Handlebars::new();
 
-        let theme_dir = match html_config.theme {
-            Some(ref theme) => theme.to_path_buf(),
-            None => ctx.root.join("theme"),
-        };
-
         if html_config.theme.is_none()
             && maybe_wrong_theme_dir(&src_dir.join("theme")).unwrap_or(false)
         {
@@ -318,18 +555,68 @@ impl Renderer for HtmlHandlebars {
             warn!("Please move your theme files to `./theme` for them to continue being used");
         }
 
-        let theme = theme::Theme::new(theme_dir);
+        let theme = theme::Theme::layered(&theme_layers(&ctx.root, &html_config)?);
+        // The block overrides go in front of every page template, so their
+        // inline partials are defined before the template uses them
+        let with_blocks = |template: &[u8]| -> Result<String> {
+            let mut source = theme.blocks.clone();
+            source.extend_from_slice(template);
+            Ok(String::from_utf8(source)?)
+        };
 
         debug!("Register the index handlebars template");
-        handlebars.register_template_string("index", String::from_utf8(theme.index.clone())?)?;
+        handlebars.register_template_string("index", with_blocks(&theme.index)?)?;
 
         debug!("Register the header handlebars template");
         handlebars.register_partial("header", String::from_utf8(theme.header.clone())?)?;
 
+        debug!("Register the theme's partials and page templates");
+        for (name, partial) in &theme.partials {
+            handlebars
+                .register_partial(name, String::from_utf8(partial.clone())?)
+                .chain_err(|| format!("Unable to register the {} partial", name))?;
+        }
+        for (name, template) in &theme.templates {
+            handlebars
+                .register_template_string(
+                    &templates::registered_name(name),
+                    with_blocks(template)?,
+                )
+                .chain_err(|| format!("Unable to register the {} template", name))?;
+        }
+        let page_templates = PageTemplates::new(&html_config, &theme)?;
+
+        debug!("Register the redirect handlebars template");
+        handlebars
+            .register_template_string("redirect", String::from_utf8(theme.redirect.clone())?)?;
+
         debug!("Register handlebars helpers");
         self.register_hbs_helpers(&mut handlebars, &html_config);
 
-        let mut data = make_data(&ctx.root, &book, &ctx.config, &html_config)?;
+        let assets = collect_assets(&theme, &html_config, &ctx.root)
+            .chain_err(|| "Unable to hash the static files")?;
+
+        // The search index only depends on the book's source, so it can be
+        // hashed like the other scripts before any page links to it
+        #[cfg(feature = "search")]
+        let (assets, search_index) = {
+            let mut assets = assets;
+            let search_config = html_config.search.clone().unwrap_or_default();
+            let index = super::search::build_index(&search_config, &book, html_config.minify)?;
+            if html_config.hash_files && search_config.copy_js {
+                assets.add("searchindex.js", index.as_bytes());
+            }
+            (assets, index)
+        };
+        let mut data = make_data(
+            &ctx.root,
+            book,
+            &ctx.config,
+            &html_config,
+            &assets,
+            &ctx.last_modified,
+        )?;
+        color_schemes.insert_data(&mut data);
 
         // Print version
         let mut print_content = String::new();
@@ -337,56 +624,513 @@ impl Renderer for HtmlHandlebars {
         fs::create_dir_all(&destination)
             .chain_err(|| "Unexpected error when constructing destination path")?;
 
+        let mut cache = if ctx.config.build.incremental {
+            BuildCache::load(destination)
+        } else {
+            BuildCache::disabled()
+        };
+
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(ctx.config.build.jobs.unwrap_or(0))
+            .build()
+            .chain_err(|| "Unable to start the rendering threads")?;
+
+        let images = pool.install(|| {
+            let chapters = book.iter()
+                .filter_map(|item| match *item {
+                    BookItem::Chapter(ref ch) => Some(ch),
+                    BookItem::Separator => None,
+                })
+                .collect::<Vec<_>>();
+            ImageChecker::new(&src_dir, &chapters)
+        });
+        if html_config.images.deny_missing {
+            for missing in images.missing() {
+                error!("{}", missing);
+            }
+            if !images.missing().is_empty() {
+                bail!("Found {} missing image(s)", images.missing().len());
+            }
+        } else {
+            for missing in images.missing() {
+                warn!("{}", missing);
+            }
+        }
+
+        // Everything which affects every page, the chapter list (and therefore
+        // the TOC and prev/next links) included
+        let global_key = cache::hash(&(
+            ctx.version.as_str(),
+            &theme.index,
+            &theme.header,
+            &theme.partials,
+            &theme.templates,
+            &theme.blocks,
+            serde_json::to_string(&data)?,
+            serde_json::to_string(&html_config)?,
+            images.sizes(),
+        ));
+        let chapter_keys = book.iter()
+            .map(|item| match *item {
+                BookItem::Chapter(ref ch) => {
+                    let modified = ctx.last_modified.get(&ch.path).map(|m| (&m.datetime, &m.author));
+                    cache::hash(&(global_key, &ch.path, &ch.content, modified))
+                }
+                BookItem::Separator => global_key,
+            })
+            .collect::<Vec<_>>();
+        let print_is_fresh =
+            !html_config.print.enable || cache.is_fresh("print.html", cache::hash(&chapter_keys));
+
+        // Work out what needs rendering up front, the cache can't be shared
+        // between threads
+        let mut chapters = Vec::new();
         let mut is_index = true;
-        for item in book.iter() {
-            let ctx = RenderItemContext {
-                handlebars: &handlebars,
-                destination: destination.to_path_buf(),
-                data: data.clone(),
-                is_index: is_index,
-                html_config: html_config.clone(),
-            };
-            self.render_item(item, ctx, &mut print_content)?;
-            is_index = false;
+        for (item, &key) in book.iter().zip(&chapter_keys) {
+            if let BookItem::Chapter(ref ch) = *item {
+                let page = cache.is_fresh(ch.path.with_extension("html"), key);
+                // Don't short-circuit, `index.html` still needs recording
+                let page_is_fresh = page & (!is_index || cache.is_fresh("index.html", key));
+
+                chapters.push((ch, is_index, page_is_fresh));
+                is_index = false;
+            }
         }
 
-        // Print version
-        self.configure_print_version(&mut data, &print_content);
-        if let Some(ref title) = ctx.config.book.title {
-            data.insert("title".to_owned(), json!(title));
+        let math = if html_config.mathjax_support && html_config.math.mode == MathMode::Command {
+            Some(MathRenderer::new(&html_config.math)?)
+        } else {
+            None
+        };
+        if html_config.mathjax_support && html_config.math.mode == MathMode::Vendored {
+            self.copy_mathjax(&html_config, &ctx.root, destination, &mut cache)
+                .chain_err(|| "Unable to copy across MathJax")?;
         }
 
-        // Render the handlebars template with the data
-        debug!("Render template");
-        let rendered = handlebars.render("index", &data)?;
+        let checker = pool.install(|| {
+            LinkChecker::new(&chapters.iter().map(|&(ch, _, _)| ch).collect::<Vec<_>>())
+        });
+        let broken_links = chapters
+            .iter()
+            .flat_map(|&(ch, _, _)| checker.check(ch))
+            .collect::<Vec<_>>();
+        for broken_link in &broken_links {
+            warn!("{}", broken_link);
+        }
+        if html_config.deny_broken_links && !broken_links.is_empty() {
+            bail!("Found {} broken link(s)", broken_links.len());
+        }
+
+        let results: Vec<Result<()>> = pool.install(|| {
+            chapters
+                .par_iter()
+                .map(|&(ch, is_index, page_is_fresh)| {
+                    if page_is_fresh {
+                        debug!("Skipping unchanged {}", ch.path.display());
+                        return Ok(());
+                    }
+
+                    let ctx = RenderItemContext {
+                        handlebars: &handlebars,
+                        template: page_templates.for_chapter(&ch.path)?,
+                        destination,
+                        data: &data,
+                        is_index,
+                        html_config: &html_config,
+                        edit_url: html_config.edit_url_template.as_ref().map(|template| {
+                            edit_url(template, &ctx.config.book.src, &src_dir, &ch.path)
+                        }),
+                        last_modified: ctx.last_modified.get(&ch.path),
+                        math: math.as_ref(),
+                        images: &images,
+                    };
+                    self.render_item(ch, ctx)
+                })
+                .collect()
+        });
+
+        for result in results {
+            result?;
+        }
+
+        // Every chapter ends up on the same page when printing (or in the
+        // single file), so each one gets an anchor which its header IDs and
+        // links are namespaced with. The anchors and header IDs share one set
+        // of IDs, so they can never clash.
+        let mut header_ids = HashMap::new();
+        let chapter_anchors = chapters
+            .iter()
+            .map(|&(ch, _, _)| {
+                let anchor = utils::unique_id(&print_anchor(&ch.path), &mut header_ids);
+                (ch.path.clone(), anchor)
+            })
+            .collect::<HashMap<_, _>>();
+
+        if !print_is_fresh || html_config.single_file {
+            for (i, &(ch, _, _)) in chapters.iter().enumerate() {
+                // Top-level chapters start on a new page when printed
+                let page_break = if i > 0 && ch.parent_names.is_empty() {
+                    r#" style="break-before: page; page-break-before: always;""#
+                } else {
+                    ""
+                };
+                print_content.push_str(&format!(
+                    "<div id=\"{}\"{}></div>\n",
+                    chapter_anchors[&ch.path],
+                    page_break
+                ));
+
+                let options = utils::RenderOptions {
+                    curly_quotes: html_config.curly_quotes,
+                    path: Some("print.html"),
+                    playpen: playpen(&html_config),
+                    chapter: Some(&ch.path),
+                    chapter_anchors: Some(&chapter_anchors),
+                    highlight: html_config.highlight == Highlight::Server,
+                };
+                let mut content =
+                    utils::render_markdown_with_options(&ch.content, &options, &mut header_ids);
+                if let Some(ref math) = math {
+                    content = math.render(&content).chain_err(|| {
+                        format!("Unable to render the math in {}", ch.path.display())
+                    })?;
+                }
+                let content = images.add_attributes(
+                    &content,
+                    &ch.path,
+                    Some(Path::new("print.md")),
+                    &html_config.images,
+                );
+                print_content.push_str(&content);
+            }
+        }
+
+        if !print_is_fresh {
+            // Print version
+            let mut data = data.clone();
+            self.configure_print_version(&mut data, &print_content);
+            if let Some(ref title) = ctx.config.book.title {
+                data.insert("title".to_owned(), json!(title));
+            }
 
-        let rendered = self.post_process(rendered, "print.html", &html_config.playpen);
+            // Render the handlebars template with the data
+            debug!("Render template");
+            let rendered = render_page(&handlebars, templates::DEFAULT, &data, &html_config)?;
 
-        utils::fs::write_file(&destination, "print.html", &rendered.into_bytes())?;
-        debug!("Creating print.html ✓");
+            utils::fs::write_file(destination, "print.html", &rendered.into_bytes())?;
+            debug!("Creating print.html ✓");
+        }
+
+        let pages = chapters
+            .iter()
+            .map(|&(ch, _, _)| ch.path.with_extension("html"))
+            .map(|path| utils::fs::normalize_path(&path.to_string_lossy()))
+            .collect::<Vec<_>>();
+
+        // A book can provide its own 404 page as a chapter
+        if !pages.iter().any(|page| page == "404.html") {
+            self.render_404(&handlebars, &data, &html_config, destination, &mut cache)
+                .chain_err(|| "Unable to render the 404 page")?;
+        }
+
+        if let Some(site_url) = data.get("site_url").and_then(serde_json::Value::as_str) {
+            debug!("Creating sitemap.xml");
+            // The first chapter is also the front page, at the root of the book
+            let front_page = chapters
+                .iter()
+                .find(|&&(_, is_index, _)| is_index)
+                .map(|&(ch, _, _)| ("", ctx.last_modified.get(&ch.path)));
+            let entries = front_page
+                .into_iter()
+                .chain(
+                    chapters
+                        .iter()
+                        .zip(&pages)
+                        .map(|(&(ch, _, _), page)| (page.as_str(), ctx.last_modified.get(&ch.path))),
+                )
+                .collect::<Vec<_>>();
+            let sitemap = make_sitemap(site_url, &entries);
+            cache.write_file(destination, "sitemap.xml", sitemap.as_bytes())?;
+        }
+
+        let mut outputs = pages
+            .iter()
+            .cloned()
+            .chain(vec![
+                "index.html".to_string(),
+                "404.html".to_string(),
+                "sitemap.xml".to_string(),
+            ])
+            .collect::<HashSet<_>>();
+        if html_config.print.enable {
+            outputs.insert("print.html".to_string());
+        }
+        if html_config.single_file {
+            outputs.insert(single_file::FILE_NAME.to_string());
+        }
+        self.emit_redirects(
+            destination,
+            &handlebars,
+            &data,
+            &html_config.redirect,
+            &outputs,
+            &mut cache,
+        ).chain_err(|| "Unable to emit redirects")?;
 
         debug!("Copy static files");
-        self.copy_static_files(&destination, &theme, &html_config)
+        self.copy_static_files(destination, &theme, &html_config, &assets, &mut cache)
             .chain_err(|| "Unable to copy across static files")?;
-        self.copy_additional_css_and_js(&html_config, &ctx.root, &destination)
+        self.copy_additional_css_and_js(&html_config, &ctx.root, destination, &assets, &mut cache)
             .chain_err(|| "Unable to copy across additional CSS and JS")?;
+        if html_config.hash_files {
+            cache.write_file(destination, assets::MANIFEST, assets.manifest()?.as_bytes())?;
+        }
 
         // Render search index
         #[cfg(feature = "search")]
-        super::search::create_files(&html_config.search.unwrap_or_default(), &destination, &book)?;
+        super::search::create_files(
+            &html_config.search.clone().unwrap_or_default(),
+            destination,
+            &search_index,
+            &assets,
+            &mut cache,
+        )?;
 
         // Copy all remaining files
-        utils::fs::copy_files_except_ext(&src_dir, &destination, true, &["md"])?;
+        let patterns = ctx.config.build.exclude.iter().chain(&html_config.copy_exclude);
+        let excluded = ExcludedFiles::new(&src_dir, &patterns.collect::<Vec<_>>())?;
+        let unreferenced = unreferenced_files(&src_dir, &ctx.root, &html_config, &images)?
+            .into_iter()
+            .filter(|file| !excluded.is_excluded(file))
+            .collect::<HashSet<_>>();
+        if html_config.images.unreferenced == Unreferenced::Warn {
+            for file in &unreferenced {
+                warn!("{} isn't used by any chapter", src_dir.join(file).display());
+            }
+        }
+        let exclude = if html_config.images.unreferenced == Unreferenced::Exclude {
+            unreferenced
+        } else {
+            HashSet::new()
+        };
+        let files = utils::fs::files_except(&src_dir, destination, |path| {
+            path.extension().map_or(false, |ext| ext == "md") || exclude.contains(path)
+                || excluded.is_excluded(path)
+        })?;
+        for file in files {
+            cache.copy_file(&src_dir.join(&file), destination, &file)?;
+        }
+
+        // Everything the single file embeds is in place now
+        if html_config.single_file {
+            let mut page_anchors = pages
+                .iter()
+                .zip(&chapters)
+                .map(|(page, &(ch, _, _))| (page.clone(), chapter_anchors[&ch.path].clone()))
+                .collect::<HashMap<_, _>>();
+            if let Some(&(first, _, _)) = chapters.first() {
+                page_anchors.insert("index.html".to_string(), chapter_anchors[&first.path].clone());
+            }
+
+            let ctx = SingleFileContext {
+                handlebars: &handlebars,
+                data: &data,
+                html_config: &html_config,
+                content: &print_content,
+                page_anchors: &page_anchors,
+                destination,
+            };
+            self.render_single_file(ctx, &mut cache)
+                .chain_err(|| format!("Unable to render {}", single_file::FILE_NAME))?;
+        }
+
+        // Everything else is in place, so the service worker can list it all
+        if html_config.offline {
+            let files = offline::precache_files(destination, &cache)?;
+            let service_worker = offline::service_worker(&theme.service_worker, destination, &files)
+                .chain_err(|| "Unable to generate the service worker")?;
+            cache.write_file(destination, offline::SERVICE_WORKER, service_worker.as_bytes())?;
+        }
+
+        if html_config.precompress {
+            pool.install(|| minify::precompress(destination, &mut cache))
+                .chain_err(|| "Unable to precompress the book")?;
+        }
+
+        cache.finish().chain_err(|| "Unable to save the build cache")?;
 
         Ok(())
     }
 }
 
+/// The static files which pages link to directly (as opposed to fonts, which
+/// are found through stylesheets), and which can therefore be renamed when
+/// `output.html.hash-files` is set.
+fn linked_files<'a>(theme: &'a Theme, html_config: &HtmlConfig) -> Vec<(&'static str, &'a [u8])> {
+    let mut files: Vec<(&str, &[u8])> = vec![
+        ("book.js", &theme.js),
+        ("book.css", &theme.css),
+        ("favicon.png", &theme.favicon),
+        ("highlight.css", &theme.highlight_css),
+        ("tomorrow-night.css", &theme.tomorrow_night_css),
+        ("ayu-highlight.css", &theme.ayu_highlight_css),
+        ("clipboard.min.js", &theme.clipboard_js),
+        ("FontAwesome/css/font-awesome.css", theme::FONT_AWESOME),
+    ];
+    if html_config.highlight == Highlight::Client {
+        files.push(("highlight.js", &theme.highlight_js));
+    }
+    if html_config.offline || html_config.single_file {
+        files.push(("fonts/fonts.css", &theme.fonts_css));
+    }
+
+    // Ace is a very large dependency, so only load it when requested
+    let playpen_config = &html_config.playpen;
+    if playpen_config.editable && playpen_config.copy_js {
+        files.push(("editor.js", playpen_editor::JS));
+        files.push(("ace.js", playpen_editor::ACE_JS));
+        files.push(("mode-rust.js", playpen_editor::MODE_RUST_JS));
+        files.push(("theme-dawn.js", playpen_editor::THEME_DAWN_JS));
+        files.push(("theme-tomorrow_night.js", playpen_editor::THEME_TOMORROW_NIGHT_JS));
+    }
+
+    files
+}
+
+/// Work out what every stylesheet and script should be called, if they're
+/// getting hashes in their names.
+fn collect_assets(theme: &Theme, html_config: &HtmlConfig, root: &Path) -> Result<Assets> {
+    let mut assets = Assets::default();
+    if !html_config.hash_files {
+        return Ok(assets);
+    }
+
+    for (filename, content) in linked_files(theme, html_config) {
+        assets.add(filename, content);
+    }
+
+    #[cfg(feature = "search")]
+    {
+        if html_config.search.clone().unwrap_or_default().copy_js {
+            assets.add("searcher.js", theme::searcher::JS);
+            assets.add("mark.min.js", theme::searcher::MARK_JS);
+            assets.add("elasticlunr.min.js", theme::searcher::ELASTICLUNR_JS);
+        }
+    }
+
+    for custom_file in html_config.additional_css.iter().chain(&html_config.additional_js) {
+        let path = root.join(custom_file);
+        let mut content = Vec::new();
+        File::open(&path)
+            .and_then(|mut f| f.read_to_end(&mut content))
+            .chain_err(|| format!("Unable to read {}", path.display()))?;
+        assets.add(&utils::fs::normalize_path(&custom_file.to_string_lossy()), &content);
+    }
+
+    Ok(assets)
+}
+
+/// Every file in the source directory (other than chapters) which no chapter
+/// refers to. Additional stylesheets and scripts kept there count as used.
+fn unreferenced_files(
+    src_dir: &Path,
+    root: &Path,
+    html_config: &HtmlConfig,
+    images: &ImageChecker,
+) -> Result<HashSet<PathBuf>> {
+    if html_config.images.unreferenced == Unreferenced::Copy || !src_dir.is_dir() {
+        return Ok(HashSet::new());
+    }
+
+    let additional = html_config
+        .additional_css
+        .iter()
+        .chain(&html_config.additional_js)
+        .filter_map(|file| root.join(file).strip_prefix(src_dir).ok().map(PathBuf::from))
+        .collect::<HashSet<_>>();
+
+    let unreferenced = utils::fs::visible_files(src_dir)?
+        .into_iter()
+        .map(PathBuf::from)
+        .filter(|file| file.extension().map_or(true, |ext| ext != "md"))
+        .filter(|file| !images.is_referenced(file) && !additional.contains(file))
+        .collect();
+    Ok(unreferenced)
+}
+
+/// Render a page of the book with one of the registered templates (usually
+/// `index.hbs`), minifying it if the book is configured to be.
+fn render_page<T: Serialize>(
+    handlebars: &Handlebars,
+    template: &str,
+    data: &T,
+    html_config: &HtmlConfig,
+) -> Result<String> {
+    let rendered = handlebars.render(template, data)?;
+    if html_config.minify {
+        Ok(minify::html(&rendered))
+    } else {
+        Ok(rendered)
+    }
+}
+
+/// The template data for one chapter's page: the data shared by the whole
+/// book, with the page's own keys on top. Saves copying the shared data (the
+/// chapter tree included) for every page.
+struct PageData<'a> {
+    global: &'a serde_json::Map<String, serde_json::Value>,
+    page: serde_json::Map<String, serde_json::Value>,
+}
+
+impl<'a> Serialize for PageData<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> ::std::result::Result<S::Ok, S::Error> {
+        let shared = self.global
+            .iter()
+            .filter(|&(key, _)| !self.page.contains_key(key));
+        let mut map = serializer.serialize_map(None)?;
+        for (key, value) in self.page.iter().chain(shared) {
+            map.serialize_entry(key, value)?;
+        }
+        map.end()
+    }
+}
+
+/// Lets a helper registered through `HtmlHandlebars::register_helper()` be
+/// given to every `Handlebars` registry the renderer creates.
+struct SharedHelper(Arc<dyn HelperDef>);
+
+impl HelperDef for SharedHelper {
+    fn call_inner(
+        &self,
+        h: &Helper,
+        r: &Handlebars,
+        rc: &mut HbsRenderContext,
+    ) -> ::std::result::Result<Option<serde_json::Value>, RenderError> {
+        self.0.call_inner(h, r, rc)
+    }
+
+    fn call(&self, h: &Helper, r: &Handlebars, rc: &mut HbsRenderContext) -> HelperResult {
+        self.0.call(h, r, rc)
+    }
+}
+
+/// The playpen settings to render code blocks with, unless code shouldn't be
+/// runnable at all because the book is meant to be read offline.
+fn playpen(html_config: &HtmlConfig) -> Option<&Playpen> {
+    if html_config.offline {
+        None
+    } else {
+        Some(&html_config.playpen)
+    }
+}
+
 fn make_data(
     root: &Path,
     book: &Book,
     config: &Config,
     html_config: &HtmlConfig,
+    assets: &Assets,
+    last_modified: &BTreeMap<PathBuf, LastModified>,
 ) -> Result<serde_json::Map<String, serde_json::Value>> {
     trace!("make_data");
     let html = config.html_config().unwrap_or_default();
@@ -401,27 +1145,60 @@ fn make_data(
         "description".to_owned(),
         json!(config.book.description.clone().unwrap_or_default()),
     );
-    data.insert("favicon".to_owned(), json!("favicon.png"));
+    data.insert("favicon".to_owned(), json!(assets.name("favicon.png")));
+    if html_config.hash_files {
+        data.insert("assets".to_owned(), assets.to_json());
+    }
+    if html_config.print.enable {
+        data.insert("print_enable".to_owned(), json!(true));
+    }
+    if html_config.highlight == Highlight::Server {
+        data.insert("server_highlight".to_owned(), json!(true));
+    }
+    if html_config.offline {
+        data.insert("offline".to_owned(), json!(true));
+        data.insert("bundled_fonts".to_owned(), json!(true));
+    }
+    if let Some(ref site_url) = html_config.site_url {
+        // Make sure page paths can simply be appended
+        let site_url = format!("{}/", site_url.trim_end_matches('/'));
+        data.insert("site_url".to_owned(), json!(site_url));
+    }
     if let Some(ref livereload) = html_config.livereload_url {
         data.insert("livereload".to_owned(), json!(livereload));
     }
 
     // Add google analytics tag
     if let Some(ref ga) = config.html_config().and_then(|html| html.google_analytics) {
-        data.insert("google_analytics".to_owned(), json!(ga));
+        if html_config.offline {
+            warn!("Google Analytics doesn't work offline, ignoring `output.html.google-analytics`");
+        } else {
+            data.insert("google_analytics".to_owned(), json!(ga));
+        }
     }
 
-    if html.mathjax_support {
+    // Math rendered while building the book doesn't need MathJax, and an
+    // offline book can't get it from a CDN
+    let mathjax = match html_config.math.mode {
+        MathMode::Cdn => !html_config.offline,
+        MathMode::Vendored => true,
+        MathMode::Command => false,
+    };
+    if html_config.mathjax_support && mathjax {
         data.insert("mathjax_support".to_owned(), json!(true));
+        if html_config.math.mode == MathMode::Vendored {
+            data.insert("mathjax_vendored".to_owned(), json!(true));
+        }
     }
 
     // Add check to see if there is an additional style
-    if !html.additional_css.is_empty() {
+    if !html_config.additional_css.is_empty() {
         let mut css = Vec::new();
-        for style in &html.additional_css {
+        for style in &html_config.additional_css {
+            let style = assets.path(style);
             match style.strip_prefix(root) {
-                Ok(p) => css.push(p.to_str().expect("Could not convert to str")),
-                Err(_) => css.push(style.to_str().expect("Could not convert to str")),
+                Ok(p) => css.push(p.to_str().expect("Could not convert to str").to_string()),
+                Err(_) => css.push(style.to_str().expect("Could not convert to str").to_string()),
             }
         }
         data.insert("additional_css".to_owned(), json!(css));
@@ -431,14 +1208,16 @@ fn make_data(
     if !html.additional_js.is_empty() {
         let mut js = Vec::new();
         for script in &html.additional_js {
+            let script = assets.path(script);
             match script.strip_prefix(root) {
-                Ok(p) => js.push(p.to_str().expect("Could not convert to str")),
+                Ok(p) => js.push(p.to_str().expect("Could not convert to str").to_string()),
                 Err(_) => js.push(
                     script
                         .file_name()
                         .expect("File has a file name")
                         .to_str()
-                        .expect("Could not convert to str"),
+                        .expect("Could not convert to str")
+                        .to_string(),
                 ),
             }
         }
@@ -490,208 +1269,219 @@ fn make_data(
     }
 
     data.insert("chapters".to_owned(), json!(chapters));
+    data.insert(
+        "chapter_tree".to_owned(),
+        json!(tree::chapter_tree(&book.sections, last_modified)?),
+    );
 
     debug!("[*]: JSON constructed");
     Ok(data)
 }
 
-/// Goes through the rendered HTML, making sure all header tags are wrapped in
-/// an anchor so people can link to sections directly.
-fn build_header_links(html: &str, filepath: &str) -> String {
-    let regex = Regex::new(r"<h(\d)>(.*?)</h\d>").unwrap();
-    let mut id_counter = HashMap::new();
-
-    regex
-        .replace_all(html, |caps: &Captures| {
-            let level = caps[1]
-                .parse()
-                .expect("Regex should ensure we only ever get numbers here");
-
-            wrap_header_with_link(level, &caps[2], &mut id_counter, filepath)
-        })
-        .into_owned()
+/// The anchor a chapter gets on the print page, e.g. `format-config` for
+/// `format/config.md`.
+fn print_anchor(path: &Path) -> String {
+    let path = utils::fs::normalize_path(&path.with_extension("").to_string_lossy());
+    utils::normalize_id(&path.replace('/', "-"))
 }
 
-/// Wraps a single header tag with a link, making sure each tag gets its own
-/// unique ID by appending an auto-incremented number (if necessary).
-fn wrap_header_with_link(
-    level: usize,
-    content: &str,
-    id_counter: &mut HashMap<String, usize>,
-    filepath: &str,
-) -> String {
-    let raw_id = utils::id_from_content(content);
-
-    let id_count = id_counter.entry(raw_id.clone()).or_insert(0);
-
-    let id = match *id_count {
-        0 => raw_id,
-        other => format!("{}-{}", raw_id, other),
-    };
+/// The absolute URL of a page, if the book has a `site-url`.
+fn canonical_url(data: &serde_json::Map<String, serde_json::Value>, page: &str) -> Option<String> {
+    data.get("site_url")
+        .and_then(serde_json::Value::as_str)
+        .map(|site_url| format!("{}{}", site_url, page))
+}
 
-    *id_count += 1;
+/// Generate a `sitemap.xml` listing every page (`""` being the root of the
+/// book), along with when it was last changed (if known).
+fn make_sitemap(site_url: &str, pages: &[(&str, Option<&LastModified>)]) -> String {
+    let mut sitemap = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+    );
 
-    format!(
-        r##"<a class="header" href="{filepath}#{id}" id="{id}"><h{level}>{text}</h{level}></a>"##,
-        level = level,
-        id = id,
-        text = content,
-        filepath = filepath
-    )
-}
+    for &(page, last_modified) in pages {
+        let url = format!("{}{}", site_url, page);
+        sitemap.push_str(&format!("  <url><loc>{}</loc>", utils::escape_html(&url)));
+        if let Some(last_modified) = last_modified {
+            sitemap.push_str(&format!("<lastmod>{}</lastmod>", last_modified.datetime));
+        }
+        sitemap.push_str("</url>\n");
+    }
 
-// anchors to the same page (href="#anchor") do not work because of
-// <base href="../"> pointing to the root folder. This function *fixes*
-// that in a very inelegant way
-fn fix_anchor_links(html: &str, filepath: &str) -> String {
-    let regex = Regex::new(r##"<a([^>]+)href="#([^"]+)"([^>]*)>"##).unwrap();
-    regex
-        .replace_all(html, |caps: &Captures| {
-            let before = &caps[1];
-            let anchor = &caps[2];
-            let after = &caps[3];
-
-            format!(
-                "<a{before}href=\"{filepath}#{anchor}\"{after}>",
-                before = before,
-                filepath = filepath,
-                anchor = anchor,
-                after = after
-            )
-        })
-        .into_owned()
+    sitemap.push_str("</urlset>\n");
+    sitemap
 }
 
-// The rust book uses annotations for rustdoc to test code snippets,
-// like the following:
-// ```rust,should_panic
-// fn main() {
-//     // Code here
-// }
-// ```
-// This function replaces all commas by spaces in the code block classes
-fn fix_code_blocks(html: &str) -> String {
-    let regex = Regex::new(r##"<code([^>]+)class="([^"]+)"([^>]*)>"##).unwrap();
-    regex
-        .replace_all(html, |caps: &Captures| {
-            let before = &caps[1];
-            let classes = &caps[2].replace(",", " ");
-            let after = &caps[3];
-
-            format!(
-                r#"<code{before}class="{classes}"{after}>"#,
-                before = before,
-                classes = classes,
-                after = after
-            )
-        })
-        .into_owned()
+/// Fill in a chapter's `edit-url-template`, replacing `{path}` with the
+/// location of the chapter's source relative to the root of the book (e.g.
+/// `src/first/chapter.md`).
+fn edit_url(template: &str, src: &Path, src_dir: &Path, chapter: &Path) -> String {
+    let source = utils::fs::chapter_source_path(src_dir, chapter);
+    let path = utils::fs::normalize_path(&src.join(source).to_string_lossy());
+    template.replace("{path}", &path)
 }
 
-fn add_playpen_pre(html: &str, playpen_config: &Playpen) -> String {
-    let regex = Regex::new(r##"((?s)<code[^>]?class="([^"]+)".*?>(.*?)</code>)"##).unwrap();
-    regex
-        .replace_all(html, |caps: &Captures| {
-            let text = &caps[1];
-            let classes = &caps[2];
-            let code = &caps[3];
-
-            if (classes.contains("language-rust") && !classes.contains("ignore"))
-                || classes.contains("mdbook-runnable")
-            {
-                // wrap the contents in an external pre block
-                if playpen_config.editable && classes.contains("editable")
-                    || text.contains("fn main") || text.contains("quick_main!")
-                {
-                    format!("<pre class=\"playpen\">{}</pre>", text)
-                } else {
-                    // we need to inject our own main
-                    let (attrs, code) = partition_source(code);
-
-                    format!(
-                        "<pre class=\"playpen\"><code class=\"{}\">\n# \
-                         #![allow(unused_variables)]\n{}#fn main() {{\n{}#}}</code></pre>",
-                        classes, attrs, code
-                    )
-                }
-            } else {
-                // not language-rust, so no-op
-                text.to_owned()
-            }
-        })
-        .into_owned()
-}
+/// Turn a redirect's source (e.g. `/old/chapter.html`) into a path relative
+/// to the destination directory. Sources which would end up outside of it
+/// (`/../chapter.html`) are an error.
+fn redirect_source(original: &str) -> Result<String> {
+    let normalized = utils::fs::normalize_path(original);
+    if normalized.is_empty() || normalized.ends_with('/') {
+        bail!("The redirect source \"{}\" must be a file", original);
+    }
 
-fn partition_source(s: &str) -> (String, String) {
-    let mut after_header = false;
-    let mut before = String::new();
-    let mut after = String::new();
-
-    for line in s.lines() {
-        let trimline = line.trim();
-        let header = trimline.chars().all(|c| c.is_whitespace()) || trimline.starts_with("#![");
-        if !header || after_header {
-            after_header = true;
-            after.push_str(line);
-            after.push_str("\n");
-        } else {
-            before.push_str(line);
-            before.push_str("\n");
+    let mut parts = Vec::new();
+    for component in Path::new(normalized.trim_start_matches('/')).components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_string_lossy().into_owned()),
+            Component::CurDir => {}
+            _ => bail!(
+                "The redirect source \"{}\" must be inside the book",
+                original
+            ),
         }
     }
 
-    (before, after)
+    if parts.is_empty() {
+        bail!("The redirect source \"{}\" must be a file", original);
+    }
+    Ok(parts.join("/"))
+}
+
+/// Work out the URL a redirect stub at `source` should point at. Targets
+/// starting with `/` are relative to the root of the book, anything else
+/// (including full URLs) is used as-is.
+fn redirect_target(source: &str, target: &str) -> String {
+    if target.starts_with('/') && !target.starts_with("//") {
+        format!(
+            "{}{}",
+            utils::fs::path_to_root(source),
+            target.trim_start_matches('/')
+        )
+    } else {
+        target.to_string()
+    }
 }
 
 struct RenderItemContext<'a> {
     handlebars: &'a Handlebars,
-    destination: PathBuf,
-    data: serde_json::Map<String, serde_json::Value>,
+    template: String,
+    destination: &'a Path,
+    data: &'a serde_json::Map<String, serde_json::Value>,
     is_index: bool,
-    html_config: HtmlConfig,
+    html_config: &'a HtmlConfig,
+    edit_url: Option<String>,
+    last_modified: Option<&'a LastModified>,
+    math: Option<&'a MathRenderer>,
+    images: &'a ImageChecker,
+}
+
+struct SingleFileContext<'a> {
+    handlebars: &'a Handlebars,
+    data: &'a serde_json::Map<String, serde_json::Value>,
+    html_config: &'a HtmlConfig,
+    content: &'a str,
+    page_anchors: &'a HashMap<String, String>,
+    destination: &'a Path,
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use tempfile::Builder as TempFileBuilder;
 
     #[test]
-    fn original_build_header_links() {
+    fn page_data_overrides_the_shared_data() {
+        let mut global = serde_json::Map::new();
+        global.insert("book_title".to_owned(), json!("Book"));
+        global.insert("path_to_root".to_owned(), json!("../"));
+        let mut page = serde_json::Map::new();
+        page.insert("path_to_root".to_owned(), json!(""));
+        let data = PageData {
+            global: &global,
+            page,
+        };
+
+        assert_eq!(
+            serde_json::to_value(&data).unwrap(),
+            json!({ "book_title": "Book", "path_to_root": "" })
+        );
+    }
+
+    #[test]
+    fn edit_urls_point_at_the_chapter_source() {
+        let temp = TempFileBuilder::new().prefix("mdbook").tempdir().unwrap();
+        utils::fs::write_file(temp.path(), "first/README.md", b"").unwrap();
+        utils::fs::write_file(temp.path(), "second/index.md", b"").unwrap();
+        let template = "https://git.example.com/repo/edit/main/{path}";
+
         let inputs = vec![
-            (
-                "blah blah <h1>Foo</h1>",
-                r##"blah blah <a class="header" href="./some_chapter/some_section.html#foo" id="foo"><h1>Foo</h1></a>"##,
-            ),
-            (
-                "<h1>Foo</h1>",
-                r##"<a class="header" href="./some_chapter/some_section.html#foo" id="foo"><h1>Foo</h1></a>"##,
-            ),
-            (
-                "<h3>Foo^bar</h3>",
-                r##"<a class="header" href="./some_chapter/some_section.html#foobar" id="foobar"><h3>Foo^bar</h3></a>"##,
-            ),
-            (
-                "<h4></h4>",
-                r##"<a class="header" href="./some_chapter/some_section.html#" id=""><h4></h4></a>"##,
-            ),
-            (
-                "<h4><em>Hï</em></h4>",
-                r##"<a class="header" href="./some_chapter/some_section.html#hï" id="hï"><h4><em>Hï</em></h4></a>"##,
-            ),
-            (
-                "<h1>Foo</h1><h3>Foo</h3>",
-                r##"<a class="header" href="./some_chapter/some_section.html#foo" id="foo"><h1>Foo</h1></a><a class="header" href="./some_chapter/some_section.html#foo-1" id="foo-1"><h3>Foo</h3></a>"##,
-            ),
+            ("intro.md", "https://git.example.com/repo/edit/main/src/intro.md"),
+            ("first/index.md", "https://git.example.com/repo/edit/main/src/first/README.md"),
+            ("second/index.md", "https://git.example.com/repo/edit/main/src/second/index.md"),
         ];
 
-        for (src, should_be) in inputs {
-            let filepath = "./some_chapter/some_section.html";
-            let got = build_header_links(&src, filepath);
+        for (chapter, should_be) in inputs {
+            let got = edit_url(template, Path::new("src"), temp.path(), Path::new(chapter));
             assert_eq!(got, should_be);
+        }
+    }
 
-            // This is redundant for most cases
-            let got = fix_anchor_links(&got, filepath);
-            assert_eq!(got, should_be);
+    #[test]
+    fn sitemap_lists_every_page() {
+        let last_modified = LastModified {
+            timestamp: 1527422592,
+            datetime: String::from("2018-05-27T14:03:12+02:00"),
+            date: String::from("2018-05-27"),
+            author: String::from("Jane Doe"),
+        };
+        let pages = vec![
+            ("", Some(&last_modified)),
+            ("intro.html", Some(&last_modified)),
+            ("a&b/c.html", None),
+        ];
+        let should_be = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">
+  <url><loc>https://example.com/book/</loc><lastmod>2018-05-27T14:03:12+02:00</lastmod></url>
+  <url><loc>https://example.com/book/intro.html</loc><lastmod>2018-05-27T14:03:12+02:00</lastmod></url>
+  <url><loc>https://example.com/book/a&amp;b/c.html</loc></url>
+</urlset>
+";
+
+        assert_eq!(make_sitemap("https://example.com/book/", &pages), should_be);
+    }
+
+    #[test]
+    fn print_anchors_are_derived_from_the_chapter_path() {
+        assert_eq!(print_anchor(Path::new("intro.md")), "intro");
+        assert_eq!(print_anchor(Path::new("format/config.md")), "format-config");
+        assert_eq!(print_anchor(Path::new("01 Getting Started.md")), "a01-getting-started");
+    }
+
+    #[test]
+    fn redirect_sources_are_relative_to_the_destination() {
+        assert_eq!(redirect_source("/old/chapter.html").unwrap(), "old/chapter.html");
+        assert_eq!(redirect_source("old.html").unwrap(), "old.html");
+        assert_eq!(redirect_source("/./old//chapter.html").unwrap(), "old/chapter.html");
+
+        for outside in &["/../x.html", "../../etc.html", "old/../../x.html", "/old/", "/", ""] {
+            assert!(redirect_source(outside).is_err(), "{}", outside);
+        }
+    }
+
+    #[test]
+    fn redirect_targets_are_resolved_from_the_source() {
+        let inputs = vec![
+            ("old.html", "/new.html", "new.html"),
+            ("old/chapter.html", "/new/chapter.html", "../new/chapter.html"),
+            ("a/b/c.html", "/index.html#intro", "../../index.html#intro"),
+            ("old/chapter.html", "sibling.html", "sibling.html"),
+            ("old.html", "https://example.com/", "https://example.com/"),
+        ];
+
+        for (source, target, should_be) in inputs {
+            assert_eq!(redirect_target(source, target), should_be);
         }
     }
 }
@@ -0,0 +1,47 @@
+use handlebars::{Handlebars, Helper, RenderContext, RenderError};
+
+/// Link to one of the book's stylesheets or scripts by whatever name it ended
+/// up with, relative to the page being rendered, e.g.
+/// `{{asset "book.css"}}`.
+pub fn asset(h: &Helper, _: &Handlebars, rc: &mut RenderContext) -> Result<(), RenderError> {
+    trace!("asset (handlebars helper)");
+
+    let name = h.param(0)
+        .and_then(|param| param.value().as_str())
+        .ok_or_else(|| RenderError::new("Param 0 with String type is required for asset helper"))?
+        .to_string();
+
+    let path_to_root = rc.evaluate_absolute("path_to_root", false)?
+        .as_str()
+        .unwrap_or("")
+        .to_string();
+    let renamed = rc.evaluate_absolute("assets", false)?
+        .get(&name)
+        .and_then(|renamed| renamed.as_str())
+        .map(String::from);
+
+    let link = format!("{}{}", path_to_root, renamed.unwrap_or(name));
+    rc.writer.write_all(link.as_bytes())?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assets_are_linked_by_their_new_names() {
+        let template = "{{asset \"book.css\"}} {{asset \"book.js\"}}";
+        let data = json!({
+            "path_to_root": "../",
+            "assets": { "book.css": "book.1234abcd.css" },
+        });
+
+        let mut h = Handlebars::new();
+        h.register_helper("asset", Box::new(asset));
+
+        let got = h.render_template(template, &data).unwrap();
+        assert_eq!(got, "../book.1234abcd.css ../book.js");
+    }
+}
@@ -0,0 +1,199 @@
+use std::collections::BTreeMap;
+
+use serde_json::Value;
+use handlebars::{Context, Handlebars, Helper, RenderContext, RenderError, Renderable};
+
+use renderer::html_handlebars::tree;
+use utils;
+
+/// Which chapters, relative to the current one, a helper renders its block
+/// for.
+enum Relation {
+    /// The chapters above the current one, from the top of the book down.
+    Ancestors,
+    /// The chapters directly below the current one.
+    Children,
+    /// The closest chapter before the current one with the same parent.
+    PreviousSibling,
+    /// The closest chapter after the current one with the same parent.
+    NextSibling,
+}
+
+impl Relation {
+    fn find(&self, tree: &[Value], path: &str) -> Vec<Value> {
+        let lineage = tree::lineage(tree, path);
+        let current = match lineage.last() {
+            Some(current) => *current,
+            None => return Vec::new(),
+        };
+
+        match *self {
+            Relation::Ancestors => lineage[..lineage.len() - 1]
+                .iter()
+                .map(|&chapter| chapter.clone())
+                .collect(),
+            Relation::Children => current["sub_items"]
+                .as_array()
+                .map(|items| items.iter().filter(|item| is_chapter(item)).cloned().collect())
+                .unwrap_or_default(),
+            Relation::PreviousSibling | Relation::NextSibling => {
+                let siblings = if lineage.len() == 1 {
+                    tree
+                } else {
+                    lineage[lineage.len() - 2]["sub_items"]
+                        .as_array()
+                        .map_or(&[][..], Vec::as_slice)
+                };
+                let position = match siblings.iter().position(|item| item["path"] == *path) {
+                    Some(position) => position,
+                    None => return Vec::new(),
+                };
+
+                // Drafts are skipped, like `previous` and `next` do
+                let is_page = |item: &&Value| is_chapter(item) && item["draft"] == false;
+                let sibling = match *self {
+                    Relation::PreviousSibling => siblings[..position].iter().rev().find(is_page),
+                    _ => siblings[position + 1..].iter().find(is_page),
+                };
+                sibling.into_iter().cloned().collect()
+            }
+        }
+    }
+}
+
+fn is_chapter(item: &Value) -> bool {
+    item["type"] == "chapter"
+}
+
+/// Render the helper's block once for each related chapter, with the
+/// chapter's `title`, `number` (if it has one) and `link` (unless it is a
+/// draft) as the context.
+fn render_related(
+    h: &Helper,
+    r: &Handlebars,
+    rc: &mut RenderContext,
+    relation: Relation,
+) -> Result<(), RenderError> {
+    let chapters = {
+        let tree = rc.evaluate_absolute("chapter_tree", true)?
+            .as_array()
+            .ok_or_else(|| RenderError::new("Type error for `chapter_tree`, array expected"))?;
+        let path = rc.evaluate_absolute("path", true)?
+            .as_str()
+            .ok_or_else(|| RenderError::new("Type error for `path`, string expected"))?;
+        // The tree's paths always use forward slashes
+        relation.find(tree, &utils::fs::normalize_path(path))
+    };
+
+    // Links are relative to the page being rendered
+    let path_to_root = rc.evaluate_absolute("path_to_root", false)?
+        .as_str()
+        .unwrap_or("")
+        .to_string();
+
+    let template = h.template()
+        .ok_or_else(|| RenderError::new("Error with the handlebars template"))?;
+
+    for chapter in chapters {
+        let mut context = BTreeMap::new();
+        context.insert("title".to_owned(), chapter["name"].clone());
+        if !chapter["number"].is_null() {
+            context.insert("number".to_owned(), chapter["number"].clone());
+        }
+        if chapter["draft"] == false {
+            let link = format!("{}{}", path_to_root, chapter["link"].as_str().unwrap_or(""));
+            context.insert("link".to_owned(), json!(link));
+        }
+
+        let mut local_rc = rc.with_context(Context::wraps(&context)?);
+        template.render(r, &mut local_rc)?;
+    }
+
+    Ok(())
+}
+
+pub fn breadcrumbs(h: &Helper, r: &Handlebars, rc: &mut RenderContext) -> Result<(), RenderError> {
+    trace!("breadcrumbs (handlebars helper)");
+    render_related(h, r, rc, Relation::Ancestors)
+}
+
+pub fn children(h: &Helper, r: &Handlebars, rc: &mut RenderContext) -> Result<(), RenderError> {
+    trace!("children (handlebars helper)");
+    render_related(h, r, rc, Relation::Children)
+}
+
+pub fn previous_sibling(
+    h: &Helper,
+    r: &Handlebars,
+    rc: &mut RenderContext,
+) -> Result<(), RenderError> {
+    trace!("previous_sibling (handlebars helper)");
+    render_related(h, r, rc, Relation::PreviousSibling)
+}
+
+pub fn next_sibling(h: &Helper, r: &Handlebars, rc: &mut RenderContext) -> Result<(), RenderError> {
+    trace!("next_sibling (handlebars helper)");
+    render_related(h, r, rc, Relation::NextSibling)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(template: &str, path: &str) -> String {
+        let data = json!({
+            "path": path,
+            "path_to_root": "../",
+            "chapter_tree": [
+                { "type": "chapter", "name": "Intro", "number": null, "path": "intro.md",
+                  "link": "intro.html", "draft": false, "sub_items": [] },
+                { "type": "chapter", "name": "Format", "number": "1.", "path": "format/index.md",
+                  "link": "format/index.html", "draft": false, "sub_items": [
+                    { "type": "chapter", "name": "Summary", "number": "1.1.", "path": "format/summary.md",
+                      "link": "format/summary.html", "draft": false, "sub_items": [] },
+                    { "type": "chapter", "name": "Plans", "number": "1.2.", "path": "",
+                      "link": "", "draft": true, "sub_items": [] },
+                    { "type": "chapter", "name": "Config", "number": "1.3.", "path": "format/config.md",
+                      "link": "format/config.html", "draft": false, "sub_items": [] },
+                ] },
+                { "type": "separator" },
+                { "type": "chapter", "name": "Contributors", "number": null, "path": "misc.md",
+                  "link": "misc.html", "draft": false, "sub_items": [] },
+            ],
+        });
+
+        let mut h = Handlebars::new();
+        h.register_helper("breadcrumbs", Box::new(breadcrumbs));
+        h.register_helper("children", Box::new(children));
+        h.register_helper("previous_sibling", Box::new(previous_sibling));
+        h.register_helper("next_sibling", Box::new(next_sibling));
+        h.render_template(template, &data).unwrap()
+    }
+
+    #[test]
+    fn breadcrumbs_lead_to_the_current_chapter() {
+        let template = "{{#breadcrumbs}}{{number}} {{title}} ({{link}}) > {{/breadcrumbs}}";
+        assert_eq!(render(template, "format/config.md"), "1. Format (../format/index.html) > ");
+        assert_eq!(render(template, "intro.md"), "");
+    }
+
+    #[test]
+    fn children_include_drafts_without_a_link() {
+        let template = "{{#children}}[{{title}}{{#if link}}: {{link}}{{/if}}]{{/children}}";
+        assert_eq!(
+            render(template, "format/index.md"),
+            "[Summary: ../format/summary.html][Plans][Config: ../format/config.html]"
+        );
+        assert_eq!(render(template, "format/config.md"), "");
+    }
+
+    #[test]
+    fn siblings_share_a_parent() {
+        let template =
+            "{{#previous_sibling}}{{title}}{{/previous_sibling}}|{{#next_sibling}}{{title}}{{/next_sibling}}";
+        assert_eq!(render(template, "format/summary.md"), "|Config");
+        assert_eq!(render(template, "format/config.md"), "Summary|");
+        assert_eq!(render(template, "format/index.md"), "Intro|Contributors");
+        assert_eq!(render(template, "missing.md"), "|");
+    }
+}
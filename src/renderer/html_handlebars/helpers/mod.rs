@@ -1,2 +1,4 @@
+pub mod asset;
+pub mod hierarchy;
 pub mod toc;
 pub mod navigation;
@@ -88,6 +88,12 @@ fn render(
 
     let mut context = BTreeMap::new();
 
+    // Links are relative to the page being rendered
+    let path_to_root = rc.evaluate_absolute("path_to_root", false)?
+        .as_str()
+        .unwrap_or("")
+        .to_string();
+
     chapter
         .get("name")
         .ok_or_else(|| RenderError::new("No title found for chapter in JSON data"))
@@ -101,7 +107,10 @@ fn render(
                 .with_extension("html")
                 .to_str()
                 .ok_or_else(|| RenderError::new("Link could not be converted to str"))
-                .map(|p| context.insert("link".to_owned(), json!(p.replace("\\", "/"))))
+                .map(|p| {
+                    let link = format!("{}{}", path_to_root, p.replace("\\", "/"));
+                    context.insert("link".to_owned(), json!(link))
+                })
         })?;
 
     trace!("Render template");
@@ -234,4 +243,35 @@ mod tests {
             "two: two.html|"
         );
     }
+
+    #[test]
+    fn links_are_relative_to_the_current_page() {
+        let data = json!({
+         "path": "nested/two.md",
+         "path_to_root": "../",
+         "chapters": [
+            {
+               "name": "one",
+               "path": "one.md"
+            },
+            {
+               "name": "two",
+               "path": "nested/two.md",
+            },
+            {
+               "name": "three",
+               "path": "nested/three.md"
+            }
+         ]
+      });
+
+        let mut h = Handlebars::new();
+        h.register_helper("previous", Box::new(previous));
+        h.register_helper("next", Box::new(next));
+
+        assert_eq!(
+            h.render_template(TEMPLATE, &data).unwrap(),
+            "one: ../one.html|three: ../nested/three.html"
+        );
+    }
 }
@@ -5,10 +5,40 @@ use serde_json;
 use handlebars::{Handlebars, Helper, HelperDef, RenderContext, RenderError};
 use pulldown_cmark::{html, Event, Parser, Tag};
 
+use config::Fold;
+
 // Handlebars helper to construct TOC
 #[derive(Clone, Copy)]
 pub struct RenderToc {
     pub no_section_label: bool,
+    pub fold: Fold,
+}
+
+impl RenderToc {
+    /// Whether the sub-sections following `item` start out expanded, or
+    /// `None` if it has none or they can't be collapsed. The chapters leading
+    /// to the current one are always expanded.
+    fn fold_state(
+        &self,
+        item: &BTreeMap<String, String>,
+        next: Option<&BTreeMap<String, String>>,
+        current_section: Option<&str>,
+    ) -> Option<bool> {
+        if !self.fold.enable {
+            return None;
+        }
+
+        let section = item.get("section")?;
+        let next_section = next.and_then(|next| next.get("section"))?;
+        if next_section.len() <= section.len() || !next_section.starts_with(section.as_str()) {
+            return None;
+        }
+
+        let leads_to_current =
+            current_section.map_or(false, |current| current.starts_with(section.as_str()));
+        let level = section.matches('.').count();
+        Some(leads_to_current || level <= usize::from(self.fold.level))
+    }
 }
 
 impl HelperDef for RenderToc {
@@ -24,12 +54,23 @@ impl HelperDef for RenderToc {
             .as_str()
             .ok_or_else(|| RenderError::new("Type error for `path`, string expected"))?
             .replace("\"", "");
+        let current_section = chapters
+            .iter()
+            .find(|item| item.get("path") == Some(&current))
+            .and_then(|item| item.get("section"))
+            .map(String::as_str);
+
+        // Links are relative to the page being rendered
+        let path_to_root = rc.evaluate_absolute("path_to_root", false)?
+            .as_str()
+            .unwrap_or("")
+            .to_string();
 
         rc.writer.write_all(b"<ol class=\"chapter\">")?;
 
         let mut current_level = 1;
 
-        for item in chapters {
+        for (i, item) in chapters.iter().enumerate() {
             // Spacer
             if item.get("spacer").is_some() {
                 rc.writer.write_all(b"<li class=\"spacer\"></li>")?;
@@ -48,22 +89,31 @@ impl HelperDef for RenderToc {
                     rc.writer.write_all(b"<ol class=\"section\">")?;
                     current_level += 1;
                 }
-                rc.writer.write_all(b"<li>")?;
+                rc.writer.write_all(b"<li")?;
             } else if level < current_level {
                 while level < current_level {
                     rc.writer.write_all(b"</ol>")?;
                     rc.writer.write_all(b"</li>")?;
                     current_level -= 1;
                 }
-                rc.writer.write_all(b"<li>")?;
+                rc.writer.write_all(b"<li")?;
             } else {
                 rc.writer.write_all(b"<li")?;
                 if item.get("section").is_none() {
                     rc.writer.write_all(b" class=\"affix\"")?;
                 }
-                rc.writer.write_all(b">")?;
             }
 
+            // The sub-sections are in the next `<li>`, which the stylesheet
+            // hides when this one is collapsed
+            let fold = self.fold_state(item, chapters.get(i + 1), current_section);
+            match fold {
+                Some(true) => rc.writer.write_all(b" class=\"expanded\"")?,
+                Some(false) => rc.writer.write_all(b" class=\"collapsed\"")?,
+                None => {}
+            }
+            rc.writer.write_all(b">")?;
+
             // Link
             let path_exists = if let Some(path) = item.get("path") {
                 if !path.is_empty() {
@@ -77,6 +127,7 @@ impl HelperDef for RenderToc {
                         .replace("\\", "/");
 
                     // Add link
+                    rc.writer.write_all(path_to_root.as_bytes())?;
                     rc.writer.write_all(tmp.as_bytes())?;
                     rc.writer.write_all(b"\"")?;
 
@@ -126,6 +177,16 @@ impl HelperDef for RenderToc {
                 rc.writer.write_all(b"</a>")?;
             }
 
+            if let (Some(expanded), Some(section)) = (fold, item.get("section")) {
+                write!(
+                    rc.writer,
+                    "<button class=\"toggle\" aria-label=\"Toggle section\" \
+                     aria-expanded=\"{}\" data-section=\"{}\">\
+                     <i class=\"fa fa-angle-right\" aria-hidden=\"true\"></i></button>",
+                    expanded, section
+                )?;
+            }
+
             rc.writer.write_all(b"</li>")?;
         }
         while current_level > 1 {
@@ -138,3 +199,73 @@ impl HelperDef for RenderToc {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render_toc(fold: Fold, current: &str) -> String {
+        let data = json!({
+            "path": current,
+            "path_to_root": "",
+            "chapters": [
+                { "section": "1.", "name": "One", "path": "one.md" },
+                { "section": "1.1.", "name": "One One", "path": "one/one.md" },
+                { "section": "1.1.1.", "name": "Deep", "path": "one/one/deep.md" },
+                { "section": "2.", "name": "Two", "path": "two.md" },
+                { "section": "2.1.", "name": "Two One", "path": "two/one.md" },
+            ],
+        });
+
+        let mut h = Handlebars::new();
+        let toc = RenderToc {
+            no_section_label: true,
+            fold,
+        };
+        h.register_helper("toc", Box::new(toc));
+        h.render_template("{{#toc}}{{/toc}}", &data).unwrap()
+    }
+
+    fn class_of(toc: &str, path: &str) -> String {
+        let link = toc.find(&format!("href=\"{}\"", path)).unwrap();
+        let li = toc[..link].rfind("<li").unwrap();
+        toc[li..link].to_string()
+    }
+
+    #[test]
+    fn sections_are_collapsed_below_the_fold_level() {
+        let fold = Fold {
+            enable: true,
+            level: 1,
+        };
+        let toc = render_toc(fold, "two/one.md");
+
+        assert_eq!(class_of(&toc, "one.html"), "<li class=\"expanded\"><a ");
+        assert_eq!(class_of(&toc, "one/one.html"), "<li class=\"collapsed\"><a ");
+        assert_eq!(class_of(&toc, "one/one/deep.html"), "<li><a ");
+        assert_eq!(toc.matches("<button class=\"toggle\"").count(), 3);
+        assert!(toc.contains("aria-expanded=\"false\" data-section=\"1.1.\""));
+    }
+
+    #[test]
+    fn the_current_chapter_is_never_hidden() {
+        let fold = Fold {
+            enable: true,
+            level: 0,
+        };
+        let toc = render_toc(fold, "one/one/deep.md");
+
+        assert_eq!(class_of(&toc, "one.html"), "<li class=\"expanded\"><a ");
+        assert_eq!(class_of(&toc, "one/one.html"), "<li class=\"expanded\"><a ");
+        assert_eq!(class_of(&toc, "two.html"), "<li class=\"collapsed\"><a ");
+    }
+
+    #[test]
+    fn nothing_is_collapsible_unless_folding_is_enabled() {
+        let toc = render_toc(Fold::default(), "one.md");
+
+        assert!(!toc.contains("expanded"));
+        assert!(!toc.contains("collapsed"));
+        assert!(!toc.contains("toggle"));
+    }
+}
@@ -0,0 +1,322 @@
+//! Checking the images chapters refer to actually exist, and telling browsers
+//! how big they are before they load (see `[output.html.images]`).
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use pulldown_cmark::{Event, Options, Parser, Tag, OPTION_ENABLE_FOOTNOTES, OPTION_ENABLE_TABLES};
+use rayon::prelude::*;
+use regex::{Captures, Regex};
+
+use book::Chapter;
+use config::Images;
+use utils;
+
+lazy_static! {
+    static ref IMG: Regex = Regex::new(r"(?i)<img\b[^>]*>").unwrap();
+    static ref SRC: Regex = Regex::new(r#"(?i)\ssrc\s*=\s*["']([^"']*)["']"#).unwrap();
+    static ref HREF_OR_SRC: Regex =
+        Regex::new(r#"(?i)\s(?:href|src)\s*=\s*["']([^"']*)["']"#).unwrap();
+}
+
+/// Knows which files in the source directory chapters refer to, and the size
+/// of every image among them.
+#[derive(Debug, Default)]
+pub struct ImageChecker {
+    /// Every file chapters refer to (relative to the source directory), along
+    /// with its width and height if it's an image we know how to measure.
+    files: BTreeMap<PathBuf, Option<(u32, u32)>>,
+    missing: Vec<String>,
+}
+
+impl ImageChecker {
+    pub fn new(src_dir: &Path, chapters: &[&Chapter]) -> ImageChecker {
+        let references = chapters
+            .par_iter()
+            .map(|ch| (ch, collect_references(&ch.content)))
+            .collect::<Vec<_>>();
+
+        let mut files = BTreeMap::new();
+        let mut missing = Vec::new();
+        for (ch, references) in references {
+            for (url, is_image) in references {
+                let path = match local_path(&ch.path, &url) {
+                    Some(path) => path,
+                    None => continue,
+                };
+
+                if src_dir.join(&path).is_file() {
+                    files.insert(path, None);
+                } else if is_image {
+                    missing.push(format!(
+                        "Missing image in {}: \"{}\" ({} doesn't exist)",
+                        ch.path.display(),
+                        url,
+                        src_dir.join(&path).display()
+                    ));
+                }
+            }
+        }
+
+        let sizes = files
+            .par_iter()
+            .map(|(path, _)| {
+                let size = fs::read(src_dir.join(path))
+                    .ok()
+                    .and_then(|content| dimensions(&content));
+                (path.clone(), size)
+            })
+            .collect::<Vec<_>>();
+        files.extend(sizes);
+
+        ImageChecker { files, missing }
+    }
+
+    /// A description of every image a chapter refers to which doesn't exist.
+    pub fn missing(&self) -> &[String] {
+        &self.missing
+    }
+
+    /// Does any chapter refer to this file (relative to the source
+    /// directory)?
+    pub fn is_referenced(&self, path: &Path) -> bool {
+        self.files.contains_key(path)
+    }
+
+    /// The size of every image chapters refer to, which pages depend on when
+    /// their dimensions get added.
+    pub fn sizes(&self) -> &BTreeMap<PathBuf, Option<(u32, u32)>> {
+        &self.files
+    }
+
+    /// Add the attributes `config` asks for to every `<img>` in some HTML
+    /// rendered from the chapter at `chapter`. When the chapter shares a page
+    /// with others, images embedded with markdown have been made relative to
+    /// that `page` (e.g. `print.md`), while those in raw HTML still aren't.
+    pub fn add_attributes(
+        &self,
+        html: &str,
+        chapter: &Path,
+        page: Option<&Path>,
+        config: &Images,
+    ) -> String {
+        if !config.dimensions && !config.lazy_loading {
+            return html.to_string();
+        }
+
+        IMG.replace_all(html, |caps: &Captures| {
+            let tag = &caps[0];
+            let mut attributes = String::new();
+
+            let size = SRC.captures(tag).and_then(|src| {
+                let size = |base: &Path| {
+                    local_path(base, &src[1]).and_then(|path| self.files.get(&path).cloned())
+                };
+                page.and_then(&size).or_else(|| size(chapter)).and_then(|size| size)
+            });
+            if let Some((width, height)) = size {
+                if config.dimensions && !has_attribute(tag, "width") && !has_attribute(tag, "height") {
+                    attributes.push_str(&format!(" width=\"{}\" height=\"{}\"", width, height));
+                }
+            }
+            if config.lazy_loading && !has_attribute(tag, "loading") {
+                attributes.push_str(" loading=\"lazy\"");
+            }
+
+            format!("{}{}{}", &tag[..4], attributes, &tag[4..])
+        }).into_owned()
+    }
+}
+
+/// Every URL some markdown refers to, and whether it's meant to be an image.
+fn collect_references(text: &str) -> Vec<(String, bool)> {
+    let mut opts = Options::empty();
+    opts.insert(OPTION_ENABLE_TABLES);
+    opts.insert(OPTION_ENABLE_FOOTNOTES);
+
+    let mut references = Vec::new();
+    for event in Parser::new_ext(text, opts) {
+        match event {
+            Event::Start(Tag::Image(dest, _)) => references.push((dest.to_string(), true)),
+            Event::Start(Tag::Link(dest, _)) => references.push((dest.to_string(), false)),
+            Event::Html(html) | Event::InlineHtml(html) => {
+                let images = IMG.find_iter(&html)
+                    .filter_map(|tag| SRC.captures(tag.as_str()))
+                    .map(|src| src[1].to_string())
+                    .collect::<HashSet<_>>();
+                for url in HREF_OR_SRC.captures_iter(&html) {
+                    let url = url[1].to_string();
+                    let is_image = images.contains(&url);
+                    references.push((url, is_image));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    references
+}
+
+/// The file a relative URL in the chapter at `chapter` refers to, relative to
+/// the source directory. Links to other chapters don't count.
+fn local_path(chapter: &Path, url: &str) -> Option<PathBuf> {
+    if !utils::is_relative_link(url) || utils::split_md_link(url).is_some() {
+        return None;
+    }
+
+    let path = url.split(&['?', '#'][..]).next().unwrap_or("");
+    utils::fs::resolve_link(chapter, &utils::percent_decode(path))
+}
+
+fn has_attribute(tag: &str, name: &str) -> bool {
+    tag.to_ascii_lowercase()
+        .split(|ch: char| ch.is_whitespace())
+        .any(|part| part.starts_with(&format!("{}=", name)))
+}
+
+/// Work out the width and height of a PNG, GIF, JPEG, WebP or SVG image.
+fn dimensions(content: &[u8]) -> Option<(u32, u32)> {
+    let be16 = |i: usize| Some(u32::from(*content.get(i)?) << 8 | u32::from(*content.get(i + 1)?));
+    let le16 = |i: usize| Some(u32::from(*content.get(i)?) | u32::from(*content.get(i + 1)?) << 8);
+    let le24 = |i: usize| Some(le16(i)? | u32::from(*content.get(i + 2)?) << 16);
+    let be32 = |i: usize| Some(be16(i)? << 16 | be16(i + 2)?);
+
+    if content.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some((be32(16)?, be32(20)?))
+    } else if content.starts_with(b"GIF87a") || content.starts_with(b"GIF89a") {
+        Some((le16(6)?, le16(8)?))
+    } else if content.starts_with(b"\xff\xd8") {
+        // Look for the "start of frame" segment
+        let mut i = 2;
+        loop {
+            while *content.get(i)? == 0xff && *content.get(i + 1)? == 0xff {
+                i += 1;
+            }
+            let marker = *content.get(i + 1)?;
+            match marker {
+                0xc0..=0xcf if marker != 0xc4 && marker != 0xc8 && marker != 0xcc => {
+                    return Some((be16(i + 7)?, be16(i + 5)?));
+                }
+                0x01 | 0xd0..=0xd9 => i += 2,
+                _ => i += 2 + be16(i + 2)? as usize,
+            }
+        }
+    } else if content.starts_with(b"RIFF") && content.get(8..12) == Some(b"WEBP") {
+        match content.get(12..16)? {
+            b"VP8 " => Some((le16(26)? & 0x3fff, le16(28)? & 0x3fff)),
+            b"VP8L" => {
+                let bits = u32::from(*content.get(21)?) | u32::from(*content.get(22)?) << 8
+                    | u32::from(*content.get(23)?) << 16
+                    | u32::from(*content.get(24)?) << 24;
+                Some((1 + (bits & 0x3fff), 1 + (bits >> 14 & 0x3fff)))
+            }
+            b"VP8X" => Some((1 + le24(24)?, 1 + le24(27)?)),
+            _ => None,
+        }
+    } else {
+        svg_dimensions(&String::from_utf8_lossy(content.get(..4096).unwrap_or(content)))
+    }
+}
+
+/// SVGs say how big they are with `width` and `height` attributes or, failing
+/// that, a `viewBox`.
+fn svg_dimensions(svg: &str) -> Option<(u32, u32)> {
+    lazy_static! {
+        static ref SVG: Regex = Regex::new(r"(?s)<svg\b[^>]*>").unwrap();
+        static ref WIDTH: Regex = Regex::new(r#"\swidth\s*=\s*["']([0-9.]+)(?:px)?["']"#).unwrap();
+        static ref HEIGHT: Regex =
+            Regex::new(r#"\sheight\s*=\s*["']([0-9.]+)(?:px)?["']"#).unwrap();
+        static ref VIEW_BOX: Regex = Regex::new(
+            r#"\sviewBox\s*=\s*["'][-0-9.]+[\s,]+[-0-9.]+[\s,]+([0-9.]+)[\s,]+([0-9.]+)["']"#
+        ).unwrap();
+    }
+
+    let tag = SVG.find(svg)?.as_str();
+    let number = |caps: Option<Captures>, i: usize| -> Option<u32> {
+        caps?.get(i)?.as_str().parse::<f64>().ok().map(|n| n.round() as u32)
+    };
+
+    match (number(WIDTH.captures(tag), 1), number(HEIGHT.captures(tag), 1)) {
+        (Some(width), Some(height)) => Some((width, height)),
+        _ => Some((
+            number(VIEW_BOX.captures(tag), 1)?,
+            number(VIEW_BOX.captures(tag), 2)?,
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::Builder as TempFileBuilder;
+
+    #[test]
+    fn image_sizes_are_read_from_their_headers() {
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR\0\0\x01\x2c\0\0\0\xc8\x08\x06\0\0\0";
+        let gif = b"GIF89a\x40\x01\xf0\x00";
+        let jpeg = b"\xff\xd8\xff\xe0\x00\x04ab\xff\xc0\x00\x11\x08\x02\x58\x03\x20\x03";
+        let svg = br#"<?xml version="1.0"?><svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 24.5 12">"#;
+
+        assert_eq!(dimensions(png), Some((300, 200)));
+        assert_eq!(dimensions(gif), Some((320, 240)));
+        assert_eq!(dimensions(jpeg), Some((800, 600)));
+        assert_eq!(dimensions(svg), Some((25, 12)));
+        assert_eq!(dimensions(b"not an image"), None);
+    }
+
+    #[test]
+    fn missing_images_are_reported_and_others_get_their_sizes() {
+        let temp = TempFileBuilder::new().prefix("mdbook").tempdir().unwrap();
+        let gif = b"GIF89a\x40\x01\xf0\x00";
+        utils::fs::write_file(temp.path(), "first/images/cat.gif", gif).unwrap();
+        utils::fs::write_file(temp.path(), "files/my notes.txt", b"").unwrap();
+
+        let chapter = Chapter::new(
+            "First",
+            String::from(
+                "![cat](images/cat.gif) ![dog](images/dog.png) ![remote](https://example.com/a.png)\n\
+                 [notes](../files/my%20notes.txt) [second](../second.md)\n\n\
+                 <img src=\"missing.svg\" alt=\"Missing\">",
+            ),
+            "first/index.md",
+            Vec::new(),
+        );
+        let checker = ImageChecker::new(temp.path(), &[&chapter]);
+
+        assert_eq!(checker.missing().len(), 2);
+        assert!(checker.missing()[0].starts_with("Missing image in first/index.md: \"images/dog.png\""));
+        assert!(checker.missing()[1].starts_with("Missing image in first/index.md: \"missing.svg\""));
+        assert!(checker.is_referenced(Path::new("files/my notes.txt")));
+        assert!(!checker.is_referenced(Path::new("second.md")));
+
+        let config = Images {
+            dimensions: true,
+            lazy_loading: true,
+            ..Default::default()
+        };
+        let html = "<p><img src=\"images/cat.gif\" alt=\"cat\" /> <img src=\"a.png\" width=\"5\"></p>";
+        assert_eq!(
+            checker.add_attributes(html, Path::new("first/index.md"), None, &config),
+            "<p><img width=\"320\" height=\"240\" loading=\"lazy\" src=\"images/cat.gif\" \
+             alt=\"cat\" /> <img loading=\"lazy\" src=\"a.png\" width=\"5\"></p>"
+        );
+
+        // On the print page only markdown images are relative to the page
+        let config = Images {
+            dimensions: true,
+            ..Default::default()
+        };
+        let html = "<img src=\"first/images/cat.gif\" /><img src=\"images/cat.gif\">";
+        assert_eq!(
+            checker.add_attributes(
+                html,
+                Path::new("first/index.md"),
+                Some(Path::new("print.md")),
+                &config
+            ),
+            "<img width=\"320\" height=\"240\" src=\"first/images/cat.gif\" />\
+             <img width=\"320\" height=\"240\" src=\"images/cat.gif\">"
+        );
+    }
+}
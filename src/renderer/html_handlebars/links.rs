@@ -0,0 +1,156 @@
+//! Checking that links between chapters (e.g. `[see](../other.md#section)`)
+//! actually lead somewhere.
+
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+
+use pulldown_cmark::{Event, Options, Parser, Tag, OPTION_ENABLE_FOOTNOTES, OPTION_ENABLE_TABLES};
+use rayon::prelude::*;
+
+use book::Chapter;
+use utils;
+
+/// Knows about every chapter in the book and the IDs of their headers.
+#[derive(Debug, Default)]
+pub struct LinkChecker {
+    chapters: HashMap<PathBuf, HashSet<String>>,
+}
+
+impl LinkChecker {
+    pub fn new(chapters: &[&Chapter]) -> LinkChecker {
+        let chapters = chapters
+            .par_iter()
+            .map(|ch| (ch.path.clone(), utils::header_ids(&ch.content)))
+            .collect();
+
+        LinkChecker { chapters }
+    }
+
+    /// Check every link to another markdown file in a chapter, returning a
+    /// description of each one which is broken.
+    pub fn check(&self, ch: &Chapter) -> Vec<String> {
+        let mut broken = Vec::new();
+
+        for (dest, text) in collect_links(&ch.content) {
+            // Links within the chapter itself
+            if dest.starts_with('#') {
+                let fragment = utils::percent_decode(&dest[1..]);
+                let own_ids = self.chapters.get(&ch.path);
+                if !fragment.is_empty() && !own_ids.map_or(false, |ids| ids.contains(&fragment)) {
+                    broken.push(format!(
+                        "Broken link in {}: \"{}\" ({}) refers to a heading which doesn't \
+                         exist in this chapter",
+                        ch.path.display(),
+                        text,
+                        dest
+                    ));
+                }
+                continue;
+            }
+
+            let (path, fragment) = match utils::split_md_link(&dest) {
+                Some(link) => link,
+                None => continue,
+            };
+
+            let target = utils::fs::resolve_link(&ch.path, &utils::percent_decode(path));
+            let ids = match target.as_ref().and_then(|target| self.chapters.get(target)) {
+                Some(ids) => ids,
+                None => {
+                    broken.push(format!(
+                        "Broken link in {}: \"{}\" ({}) doesn't point to a chapter in the book",
+                        ch.path.display(),
+                        text,
+                        dest
+                    ));
+                    continue;
+                }
+            };
+
+            if let Some(fragment) = fragment.map(utils::percent_decode) {
+                if !fragment.is_empty() && !ids.contains(&fragment) {
+                    broken.push(format!(
+                        "Broken link in {}: \"{}\" ({}) refers to a heading which doesn't \
+                         exist in {}",
+                        ch.path.display(),
+                        text,
+                        dest,
+                        path
+                    ));
+                }
+            }
+        }
+
+        broken
+    }
+}
+
+/// Find every link in some markdown, along with its text.
+fn collect_links(text: &str) -> Vec<(String, String)> {
+    let mut opts = Options::empty();
+    opts.insert(OPTION_ENABLE_TABLES);
+    opts.insert(OPTION_ENABLE_FOOTNOTES);
+
+    let mut links = Vec::new();
+    let mut current: Option<(String, String)> = None;
+
+    for event in Parser::new_ext(text, opts) {
+        match event {
+            Event::Start(Tag::Link(dest, _)) => current = Some((dest.to_string(), String::new())),
+            Event::End(Tag::Link(..)) => links.extend(current.take()),
+            Event::Text(text) | Event::InlineHtml(text) => {
+                if let Some((_, ref mut link_text)) = current {
+                    link_text.push_str(&text);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    links
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn broken_links_are_reported() {
+        let first = Chapter::new(
+            "First",
+            String::from(
+                "# First\n\n[fine](second/index.md#some-section) [also fine](#first)\n\
+                 [missing](nope.md) [bad *anchor*](second/index.md#nope) [nowhere](#nope)\n\
+                 [encoded](my%20file.md#h%C3%AF)",
+            ),
+            "first.md",
+            Vec::new(),
+        );
+        let second = Chapter::new(
+            "Second",
+            String::from("# Second\n## Some Section\n\n[back](../first.md#first)"),
+            "second/index.md",
+            Vec::new(),
+        );
+        let third = Chapter::new(
+            "Third",
+            String::from("## Hï"),
+            "my file.md",
+            Vec::new(),
+        );
+        let checker = LinkChecker::new(&[&first, &second, &third]);
+
+        assert_eq!(
+            checker.check(&first),
+            vec![
+                "Broken link in first.md: \"missing\" (nope.md) doesn't point to a chapter in \
+                 the book",
+                "Broken link in first.md: \"bad anchor\" (second/index.md#nope) refers to a \
+                 heading which doesn't exist in second/index.md",
+                "Broken link in first.md: \"nowhere\" (#nope) refers to a heading which \
+                 doesn't exist in this chapter",
+            ]
+        );
+        assert!(checker.check(&second).is_empty());
+    }
+}
@@ -0,0 +1,203 @@
+//! Rendering math (e.g. `\( \int x dx \)`) while the book is being built, for
+//! `output.html.math.mode = "command"`.
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+
+use regex::{Captures, Regex};
+use shlex::Shlex;
+
+use config::Math;
+use errors::*;
+
+/// Runs equations through the user's commands, remembering the results so
+/// each equation only gets rendered once.
+#[derive(Debug)]
+pub struct MathRenderer {
+    command: String,
+    display_command: String,
+    rendered: Mutex<HashMap<(String, bool), String>>,
+}
+
+impl MathRenderer {
+    pub fn new(config: &Math) -> Result<MathRenderer> {
+        let command = match config.command {
+            Some(ref command) => command.clone(),
+            None => bail!("Rendering math with a command requires `output.html.math.command`"),
+        };
+        let display_command = config.display_command.clone().unwrap_or_else(|| command.clone());
+
+        Ok(MathRenderer {
+            command,
+            display_command,
+            rendered: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Replace every equation in a rendered chapter with its HTML.
+    pub fn render(&self, html: &str) -> Result<String> {
+        replace_math(html, |tex, display| self.render_equation(tex, display))
+    }
+
+    fn render_equation(&self, tex: &str, display: bool) -> Result<String> {
+        let key = (tex.to_string(), display);
+        if let Some(rendered) = self.rendered.lock().unwrap().get(&key) {
+            return Ok(rendered.clone());
+        }
+
+        let command = if display {
+            &self.display_command
+        } else {
+            &self.command
+        };
+        let rendered = run(command, tex)?;
+
+        self.rendered
+            .lock()
+            .unwrap()
+            .insert(key, rendered.clone());
+        Ok(rendered)
+    }
+}
+
+/// Pipe some TeX through a command, returning whatever it printed.
+fn run(command: &str, tex: &str) -> Result<String> {
+    let mut words = Shlex::new(command);
+    let executable = match words.next() {
+        Some(e) => e,
+        None => bail!("Command string was empty"),
+    };
+
+    let mut child = Command::new(executable)
+        .args(words)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .chain_err(|| format!("Unable to start \"{}\"", command))?;
+
+    {
+        let mut stdin = child.stdin.take().expect("Child has stdin");
+        stdin
+            .write_all(tex.as_bytes())
+            .chain_err(|| format!("Unable to send the equation to \"{}\"", command))?;
+    }
+
+    let output = child
+        .wait_with_output()
+        .chain_err(|| format!("Error waiting for \"{}\"", command))?;
+    if !output.status.success() {
+        let mut msg = format!("\"{}\" failed to render `{}` ({})", command, tex, output.status);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if !stderr.trim().is_empty() {
+            msg.push_str(&format!(": {}", stderr.trim()));
+        }
+        bail!(msg);
+    }
+
+    Ok(String::from_utf8(output.stdout)?.trim().to_string())
+}
+
+/// Find the equations in some HTML the same way MathJax would, skipping code
+/// and scripts, and replace them with whatever `render` returns. Equations
+/// can't contain tags, so math which markdown turned into `<em>` won't be
+/// found.
+fn replace_math<F>(html: &str, mut render: F) -> Result<String>
+where
+    F: FnMut(&str, bool) -> Result<String>,
+{
+    lazy_static! {
+        static ref TAG: Regex = Regex::new(r"<(/?)([a-zA-Z0-9]+)[^>]*>").unwrap();
+        static ref MATH: Regex =
+            Regex::new(r"(?s)\\\((.+?)\\\)|\\\[(.+?)\\\]|\$\$(.+?)\$\$").unwrap();
+    }
+
+    let mut out = String::with_capacity(html.len());
+    let mut skip_depth = 0usize;
+    let mut last = 0;
+
+    let mut replace_in = |text: &str, out: &mut String| -> Result<()> {
+        let mut last = 0;
+        for caps in MATH.captures_iter(text) {
+            let whole = caps.get(0).unwrap();
+            let (tex, display) = equation(&caps);
+
+            out.push_str(&text[last..whole.start()]);
+            out.push_str(&render(&unescape_html(tex.trim()), display)?);
+            last = whole.end();
+        }
+        out.push_str(&text[last..]);
+        Ok(())
+    };
+
+    for tag in TAG.captures_iter(html) {
+        let whole = tag.get(0).unwrap();
+        let text = &html[last..whole.start()];
+        if skip_depth == 0 {
+            replace_in(text, &mut out)?;
+        } else {
+            out.push_str(text);
+        }
+        out.push_str(whole.as_str());
+        last = whole.end();
+
+        let name = tag[2].to_ascii_lowercase();
+        if ["pre", "code", "script", "style", "textarea"].contains(&name.as_str()) {
+            if &tag[1] == "/" {
+                skip_depth = skip_depth.saturating_sub(1);
+            } else {
+                skip_depth += 1;
+            }
+        }
+    }
+
+    let text = &html[last..];
+    if skip_depth == 0 {
+        replace_in(text, &mut out)?;
+    } else {
+        out.push_str(text);
+    }
+
+    Ok(out)
+}
+
+/// Get an equation's TeX and whether it should be displayed as a block.
+fn equation<'t>(caps: &Captures<'t>) -> (&'t str, bool) {
+    match (caps.get(1), caps.get(2), caps.get(3)) {
+        (Some(inline), _, _) => (inline.as_str(), false),
+        (_, Some(display), _) | (_, _, Some(display)) => (display.as_str(), true),
+        _ => unreachable!(),
+    }
+}
+
+/// Undo the escaping done by pulldown-cmark, so commands see the TeX that was
+/// written.
+fn unescape_html(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn equations_outside_of_code_are_rendered() {
+        let html = "<p>Inline \\( a &lt; b \\), display $$x^2$$ and \\[ y \\]</p>\n\
+                    <pre><code>\\( left alone \\)</code></pre>";
+        let should_be = "<p>Inline [inline: a < b], display [display: x^2] and \
+                         [display: y]</p>\n<pre><code>\\( left alone \\)</code></pre>";
+
+        let got = replace_math(html, |tex, display| {
+            let kind = if display { "display" } else { "inline" };
+            Ok(format!("[{}: {}]", kind, tex))
+        }).unwrap();
+
+        assert_eq!(got, should_be);
+    }
+}
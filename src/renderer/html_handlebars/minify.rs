@@ -0,0 +1,467 @@
+//! Making the book smaller, for `output.html.minify = true` and
+//! `output.html.precompress = true`.
+//!
+//! The minifiers are deliberately cautious. They only throw away comments and
+//! whitespace which can't change how a page looks or what a script does, so
+//! they don't need to understand much more than where strings start and end.
+
+use std::borrow::Cow;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use brotli::CompressorWriter;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use rayon::prelude::*;
+use regex::Regex;
+
+use errors::*;
+use renderer::html_handlebars::cache::{self, BuildCache};
+use utils;
+
+/// What gets added to the names of a file's precompressed copies.
+pub const GZIP_EXTENSION: &str = ".gz";
+pub const BROTLI_EXTENSION: &str = ".br";
+
+/// The kinds of files which are worth compressing ahead of time.
+const COMPRESSIBLE: &[&str] = &[
+    "html", "css", "js", "json", "svg", "xml", "txt", "ttf", "eot", "otf"
+];
+
+/// Elements whose contents are left exactly as they are.
+const PREFORMATTED: &[&str] = &["pre", "code", "script", "style", "textarea"];
+
+/// Minify one of the theme's stylesheets or scripts, going by its name.
+/// Anything which looks like it was minified already (e.g. `highlight.js`)
+/// is returned unchanged.
+pub fn file<'a>(name: &str, content: &'a [u8]) -> Cow<'a, [u8]> {
+    let text = match ::std::str::from_utf8(content) {
+        Ok(text) => text,
+        Err(_) => return Cow::Borrowed(content),
+    };
+    if name.ends_with(".min.js") || text.lines().any(|line| line.len() > 1000) {
+        return Cow::Borrowed(content);
+    }
+
+    if name.ends_with(".css") {
+        Cow::Owned(css(text).into_bytes())
+    } else if name.ends_with(".js") {
+        Cow::Owned(js(text).into_bytes())
+    } else {
+        Cow::Borrowed(content)
+    }
+}
+
+/// Remove comments and collapse whitespace in a rendered page, leaving code
+/// blocks, scripts and stylesheets alone.
+pub fn html(html: &str) -> String {
+    lazy_static! {
+        static ref TOKEN: Regex =
+            Regex::new(r"(?s)<!--.*?-->|<(/?)([a-zA-Z0-9]+)[^>]*>").unwrap();
+    }
+
+    let mut out = String::with_capacity(html.len());
+    let mut preformatted = 0usize;
+    let mut last = 0;
+
+    for token in TOKEN.captures_iter(html) {
+        let whole = token.get(0).unwrap();
+        push_text(&html[last..whole.start()], preformatted > 0, &mut out);
+        last = whole.end();
+
+        let name = match token.get(2) {
+            Some(name) => name.as_str().to_ascii_lowercase(),
+            None => {
+                // Comments are dropped, unless they're conditional comments
+                // for old versions of IE or part of a script
+                if preformatted > 0 || whole.as_str().starts_with("<!--[if") {
+                    out.push_str(whole.as_str());
+                }
+                continue;
+            }
+        };
+
+        out.push_str(whole.as_str());
+        if PREFORMATTED.contains(&name.as_str()) {
+            if &token[1] == "/" {
+                preformatted = preformatted.saturating_sub(1);
+            } else {
+                preformatted += 1;
+            }
+        }
+    }
+    push_text(&html[last..], preformatted > 0, &mut out);
+
+    out
+}
+
+/// Add some text from between tags, collapsing each run of whitespace into a
+/// single space (or newline, if it spanned more than one line).
+fn push_text(text: &str, preformatted: bool, out: &mut String) {
+    if preformatted {
+        out.push_str(text);
+        return;
+    }
+
+    let mut whitespace: Option<char> = None;
+    for ch in text.chars() {
+        if ch.is_whitespace() {
+            whitespace = match (whitespace, ch) {
+                (Some('\n'), _) | (_, '\n') => Some('\n'),
+                _ => Some(' '),
+            };
+        } else {
+            if let Some(whitespace) = whitespace.take() {
+                push_whitespace(whitespace, out);
+            }
+            out.push(ch);
+        }
+    }
+    if let Some(whitespace) = whitespace {
+        push_whitespace(whitespace, out);
+    }
+}
+
+/// Add a space or newline, merging it with any whitespace which came before a
+/// comment that was removed.
+fn push_whitespace(whitespace: char, out: &mut String) {
+    match out.chars().last() {
+        Some('\n') => {}
+        Some(' ') => {
+            out.pop();
+            out.push(whitespace);
+        }
+        _ => out.push(whitespace),
+    }
+}
+
+/// Remove comments (other than `/*! ... */` license headers) and any
+/// whitespace which isn't needed from a stylesheet.
+pub fn css(css: &str) -> String {
+    let chars = css.chars().collect::<Vec<_>>();
+    let mut out = String::with_capacity(css.len());
+    let mut space = false;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let ch = chars[i];
+
+        if ch == '/' && chars.get(i + 1) == Some(&'*') {
+            let end = find(&chars, i + 2, &['*', '/']).map_or(chars.len(), |end| end + 2);
+            if chars.get(i + 2) == Some(&'!') {
+                out.extend(&chars[i..end]);
+                out.push('\n');
+            } else {
+                space = true;
+            }
+            i = end;
+            continue;
+        }
+        if ch.is_whitespace() {
+            space = true;
+            i += 1;
+            continue;
+        }
+
+        // A space before `:` might separate a selector from a pseudo-class,
+        // and one after `)` a pseudo-class from the next selector
+        let last = out.chars().last();
+        if space && last.map_or(false, |last| !"{};:,>(\n".contains(last))
+            && !"{};,>)".contains(ch)
+        {
+            out.push(' ');
+        }
+        space = false;
+
+        match ch {
+            '"' | '\'' => i = copy_string(&chars, i, &mut out),
+            '}' => {
+                if out.ends_with(';') {
+                    out.pop();
+                }
+                out.push(ch);
+                i += 1;
+            }
+            _ => {
+                out.push(ch);
+                i += 1;
+            }
+        }
+    }
+
+    out
+}
+
+/// Remove comments (other than `/*! ... */` license headers), indentation
+/// and unnecessary spaces from a script. Line breaks are kept wherever they
+/// could matter to automatic semicolon insertion.
+pub fn js(js: &str) -> String {
+    let chars = js.chars().collect::<Vec<_>>();
+    let mut out = String::with_capacity(js.len());
+    let mut space: Option<char> = None;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let ch = chars[i];
+        let next = chars.get(i + 1).cloned();
+
+        if ch == '/' && next == Some('/') {
+            i = find(&chars, i, &['\n']).unwrap_or(chars.len());
+            continue;
+        }
+        if ch == '/' && next == Some('*') {
+            let end = find(&chars, i + 2, &['*', '/']).map_or(chars.len(), |end| end + 2);
+            if chars.get(i + 2) == Some(&'!') {
+                out.extend(&chars[i..end]);
+                space = Some('\n');
+            } else if space.is_none() {
+                space = Some(' ');
+            }
+            i = end;
+            continue;
+        }
+        if ch.is_whitespace() {
+            space = match (space, ch) {
+                (Some('\n'), _) | (_, '\n') => Some('\n'),
+                _ => Some(' '),
+            };
+            i += 1;
+            continue;
+        }
+
+        if let Some(space) = space.take() {
+            if let Some(last) = out.chars().last() {
+                if needs_separator(last, ch, space) {
+                    out.push(space);
+                }
+            }
+        }
+
+        if ch == '"' || ch == '\'' || ch == '`' {
+            i = copy_string(&chars, i, &mut out);
+        } else if ch == '/' && regex_allowed(&out) {
+            i = copy_regex(&chars, i, &mut out);
+        } else {
+            out.push(ch);
+            i += 1;
+        }
+    }
+
+    out
+}
+
+/// Should the whitespace between `last` and `next` be kept in a script?
+fn needs_separator(last: char, next: char, space: char) -> bool {
+    let is_word = |ch: char| ch.is_alphanumeric() || ch == '_' || ch == '$' || !ch.is_ascii();
+
+    if space == '\n' {
+        !"{([,;".contains(last) && !"})],;".contains(next)
+    } else {
+        (is_word(last) && is_word(next)) || (last == next && (last == '+' || last == '-'))
+    }
+}
+
+/// Could a `/` coming after what has been written so far start a regular
+/// expression (rather than being a division)?
+fn regex_allowed(out: &str) -> bool {
+    let trimmed = out.trim_end();
+    match trimmed.chars().last() {
+        None => true,
+        Some(last) if "(,=:[!&|?{};+-*%<>~^".contains(last) => true,
+        Some(last) if last.is_alphanumeric() || last == '_' || last == '$' => {
+            ["return", "typeof", "case", "do", "else", "in", "of", "void"]
+                .iter()
+                .any(|keyword| {
+                    trimmed.ends_with(keyword) && !trimmed[..trimmed.len() - keyword.len()]
+                        .chars()
+                        .last()
+                        .map_or(false, |ch| ch.is_alphanumeric() || ch == '_' || ch == '$')
+                })
+        }
+        _ => false,
+    }
+}
+
+/// Copy a quoted string starting at `start`, returning where it ends.
+fn copy_string(chars: &[char], start: usize, out: &mut String) -> usize {
+    let quote = chars[start];
+    out.push(quote);
+
+    let mut i = start + 1;
+    while i < chars.len() {
+        out.push(chars[i]);
+        match chars[i] {
+            '\\' if i + 1 < chars.len() => {
+                out.push(chars[i + 1]);
+                i += 2;
+            }
+            ch if ch == quote => return i + 1,
+            _ => i += 1,
+        }
+    }
+
+    i
+}
+
+/// Copy a regular expression literal starting at `start`, returning where it
+/// ends. The flags get copied along with whatever follows.
+fn copy_regex(chars: &[char], start: usize, out: &mut String) -> usize {
+    out.push('/');
+
+    let mut in_class = false;
+    let mut i = start + 1;
+    while i < chars.len() {
+        out.push(chars[i]);
+        match chars[i] {
+            '\\' if i + 1 < chars.len() => {
+                out.push(chars[i + 1]);
+                i += 2;
+                continue;
+            }
+            '[' => in_class = true,
+            ']' => in_class = false,
+            '/' if !in_class => return i + 1,
+            '\n' => return i + 1,
+            _ => {}
+        }
+        i += 1;
+    }
+
+    i
+}
+
+/// Find where `needle` next appears in `chars`, starting from `from`.
+fn find(chars: &[char], from: usize, needle: &[char]) -> Option<usize> {
+    if from > chars.len() {
+        return None;
+    }
+
+    chars[from..]
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|i| from + i)
+}
+
+/// Write a gzipped and a brotli-compressed copy next to every text file in
+/// the book (e.g. `book.css.gz` and `book.css.br`), for web servers which can
+/// send them as they are.
+pub fn precompress(destination: &Path, cache: &mut BuildCache) -> Result<()> {
+    let mut pending = Vec::new();
+
+    for file in utils::fs::visible_files(destination)? {
+        let compressible = Path::new(&file)
+            .extension()
+            .and_then(OsStr::to_str)
+            .map_or(false, |ext| COMPRESSIBLE.contains(&ext));
+        if !compressible || cache.is_stale(&file) {
+            continue;
+        }
+
+        let path = destination.join(&file);
+        let content = fs::read(&path).chain_err(|| format!("Unable to read {}", path.display()))?;
+        let key = cache::hash(&content);
+        for extension in &[GZIP_EXTENSION, BROTLI_EXTENSION] {
+            let compressed = format!("{}{}", file, extension);
+            if cache.is_fresh(&compressed, key) {
+                trace!("Skipping unchanged {}", compressed);
+                continue;
+            }
+
+            pending.push((compressed, content.clone()));
+        }
+    }
+
+    pending
+        .par_iter()
+        .map(|(compressed, content)| {
+            utils::fs::write_file(destination, compressed, &compress(compressed, content)?)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(())
+}
+
+/// Compress a file with whichever algorithm the name of its compressed copy
+/// calls for.
+fn compress(compressed: &str, content: &[u8]) -> Result<Vec<u8>> {
+    if compressed.ends_with(BROTLI_EXTENSION) {
+        let mut encoder = CompressorWriter::new(Vec::new(), 4096, 11, 22);
+        encoder.write_all(content)?;
+        Ok(encoder.into_inner())
+    } else {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(content)?;
+        Ok(encoder.finish()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    use brotli::Decompressor;
+    use flate2::read::GzDecoder;
+    use tempfile::Builder as TempFileBuilder;
+
+    #[test]
+    fn html_whitespace_is_collapsed_outside_of_code() {
+        let input = "<!DOCTYPE html>\n<!-- Generated -->\n<div>\n    <p>Some   text</p>\n\n    \
+                     <pre><code>fn main() {\n    // Indented\n}</code></pre>\n    \
+                     <script>\n    var a = 1; // <!-- kept -->\n    </script>\n</div>\n";
+        let should_be = "<!DOCTYPE html>\n<div>\n<p>Some text</p>\n<pre><code>fn main() {\n    \
+                         // Indented\n}</code></pre>\n<script>\n    var a = 1; // <!-- kept -->\n    \
+                         </script>\n</div>\n";
+
+        assert_eq!(html(input), should_be);
+    }
+
+    #[test]
+    fn css_comments_and_whitespace_are_removed() {
+        let input = "/*! License */\n/* Normal comment */\n.a :hover, .b > .c {\n    \
+                     content: \"a  ;  b\";\n    margin: 0 auto;\n}\n\
+                     @media (max-width: 600px) and (min-width: 100px) {\n    \
+                     .d:not(.e) .f { color: red; }\n}\n";
+        let should_be = "/*! License */\n.a :hover,.b>.c{content:\"a  ;  b\";margin:0 auto}\
+                         @media (max-width:600px) and (min-width:100px){.d:not(.e) .f{color:red}}";
+
+        assert_eq!(css(input), should_be);
+    }
+
+    #[test]
+    fn js_comments_and_whitespace_are_removed() {
+        let input = "// A comment\nfunction add(a, b) {\n    /* Add them */\n    \
+                     return a + +b; // \"quoted\"\n}\n\
+                     var s = \"// not a comment\";\nvar r = /[/]\\/*/g, half = 1 / 2;\n\
+                     var x = add(1, 2)\nx++\n";
+        let should_be = "function add(a,b){return a+ +b;}\nvar s=\"// not a comment\";\
+                         var r=/[/]\\/*/g,half=1/2;var x=add(1,2)\nx++";
+
+        assert_eq!(js(input), should_be);
+    }
+
+    #[test]
+    fn text_files_get_compressed_copies() {
+        let temp = TempFileBuilder::new().prefix("mdbook").tempdir().unwrap();
+        utils::fs::write_file(temp.path(), "index.html", b"<p>Hello</p>").unwrap();
+        utils::fs::write_file(temp.path(), "img/cat.png", b"not text").unwrap();
+
+        precompress(temp.path(), &mut BuildCache::disabled()).unwrap();
+
+        let mut decompressed = String::new();
+        GzDecoder::new(fs::File::open(temp.path().join("index.html.gz")).unwrap())
+            .read_to_string(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, "<p>Hello</p>");
+        assert!(!temp.path().join("img/cat.png.gz").exists());
+
+        let mut decompressed = String::new();
+        Decompressor::new(fs::File::open(temp.path().join("index.html.br")).unwrap(), 4096)
+            .read_to_string(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, "<p>Hello</p>");
+        assert!(!temp.path().join("img/cat.png.br").exists());
+    }
+}
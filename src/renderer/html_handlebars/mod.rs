@@ -2,8 +2,19 @@
 
 pub use self::hbs_renderer::HtmlHandlebars;
 
+mod assets;
+mod cache;
+mod color_schemes;
 mod hbs_renderer;
 mod helpers;
+mod images;
+mod links;
+mod math;
+mod minify;
+mod offline;
+mod single_file;
+mod templates;
+mod tree;
 
 #[cfg(feature = "search")]
 mod search;
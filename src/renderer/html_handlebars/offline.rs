@@ -0,0 +1,73 @@
+//! Letting readers keep using the book without a network connection, for
+//! `output.html.offline = true`.
+
+use std::fs;
+use std::path::Path;
+
+use errors::*;
+use renderer::html_handlebars::cache::{self, BuildCache};
+use renderer::html_handlebars::minify;
+use utils;
+
+/// The name of the generated service worker.
+pub const SERVICE_WORKER: &str = "sw.js";
+
+/// Find every file in the book a reader could need (relative to the
+/// destination directory), leaving out hidden files, copies precompressed for
+/// the web server and anything left over from the previous build which is
+/// about to be removed.
+pub fn precache_files(destination: &Path, cache: &BuildCache) -> Result<Vec<String>> {
+    let mut files = utils::fs::visible_files(destination)?;
+
+    files.retain(|file| {
+        file != SERVICE_WORKER && !file.ends_with(minify::GZIP_EXTENSION)
+            && !file.ends_with(minify::BROTLI_EXTENSION) && !cache.is_stale(file)
+    });
+    Ok(files)
+}
+
+/// Make the service worker, prefixing the theme's script with the files to
+/// precache and a version which changes whenever any of them do.
+pub fn service_worker(template: &[u8], destination: &Path, files: &[String]) -> Result<String> {
+    let mut contents = Vec::with_capacity(files.len());
+    for file in files {
+        let path = destination.join(file);
+        let content = fs::read(&path).chain_err(|| format!("Unable to read {}", path.display()))?;
+        contents.push(content);
+    }
+    let version = format!("{:016x}", cache::hash(&(files, contents)));
+
+    let precache = json!({ "version": version, "files": files });
+    Ok(format!(
+        "var PRECACHE = {};\n\n{}",
+        precache,
+        String::from_utf8_lossy(template)
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::Builder as TempFileBuilder;
+
+    #[test]
+    fn every_visible_file_is_precached() {
+        let temp = TempFileBuilder::new().prefix("mdbook").tempdir().unwrap();
+        for file in &["index.html", "first/nested.html", ".nojekyll", "sw.js"] {
+            utils::fs::write_file(temp.path(), file, b"").unwrap();
+        }
+
+        let files = precache_files(temp.path(), &BuildCache::disabled()).unwrap();
+        assert_eq!(files, vec!["first/nested.html", "index.html"]);
+
+        let before = service_worker(b"// worker", temp.path(), &files).unwrap();
+        assert!(before.starts_with("var PRECACHE = {"));
+        assert!(before.contains(r#""files":["first/nested.html","index.html"]"#));
+        assert!(before.ends_with("\n\n// worker"));
+
+        // Changing any file in the book changes the service worker too
+        utils::fs::write_file(temp.path(), "index.html", b"changed").unwrap();
+        let after = service_worker(b"// worker", temp.path(), &files).unwrap();
+        assert_ne!(before, after);
+    }
+}
@@ -12,25 +12,40 @@ use self::elasticlunr::Index;
 use book::{Book, BookItem};
 use config::Search;
 use errors::*;
+use renderer::html_handlebars::assets::Assets;
+use renderer::html_handlebars::cache::BuildCache;
 use utils;
 use theme::searcher;
 
-/// Creates all files required for search.
-pub fn create_files(search_config: &Search, destination: &Path, book: &Book) -> Result<()> {
+/// Builds the search index, as the contents of `searchindex.js`.
+pub fn build_index(search_config: &Search, book: &Book, minify: bool) -> Result<String> {
     let mut index = Index::new(&["title", "body", "breadcrumbs"]);
 
     for item in book.iter() {
-        render_item(&mut index, &search_config, item)?;
+        render_item(&mut index, search_config, item)?;
     }
 
-    let index = write_to_js(index, &search_config)?;
-    debug!("Writing search index ✓");
+    write_to_js(index, search_config, minify)
+}
 
+/// Creates all files required for search.
+pub fn create_files(
+    search_config: &Search,
+    destination: &Path,
+    index: &str,
+    assets: &Assets,
+    cache: &mut BuildCache,
+) -> Result<()> {
     if search_config.copy_js {
-        utils::fs::write_file(destination, "searchindex.js", index.as_bytes())?;
-        utils::fs::write_file(destination, "searcher.js", searcher::JS)?;
-        utils::fs::write_file(destination, "mark.min.js", searcher::MARK_JS)?;
-        utils::fs::write_file(destination, "elasticlunr.min.js", searcher::ELASTICLUNR_JS)?;
+        cache.write_file(destination, assets.name("searchindex.js"), index.as_bytes())?;
+        debug!("Writing search index ✓");
+        cache.write_file(destination, assets.name("searcher.js"), searcher::JS)?;
+        cache.write_file(destination, assets.name("mark.min.js"), searcher::MARK_JS)?;
+        cache.write_file(
+            destination,
+            assets.name("elasticlunr.min.js"),
+            searcher::ELASTICLUNR_JS,
+        )?;
         debug!("Copying search files ✓");
     }
 
@@ -83,6 +98,7 @@ fn render_item(
     let mut body = String::new();
     let mut breadcrumbs = chapter.parent_names.clone();
     let mut footnote_numbers = HashMap::new();
+    let mut header_ids = header_ids(&chapter.content, opts).into_iter();
 
     for event in p {
         match event {
@@ -106,9 +122,18 @@ fn render_item(
             }
             Event::End(Tag::Header(i)) if i <= max_section_depth => {
                 in_header = false;
-                section_id = Some(utils::id_from_content(&heading));
+
+                let len = utils::split_custom_id(&heading).0.len();
+                heading.truncate(len);
+
+                section_id = header_ids.next();
                 breadcrumbs.push(heading.clone());
             }
+            Event::End(Tag::Header(_)) => {
+                // Deeper headers don't start a section, but still take an ID
+                header_ids.next();
+                body.push(' ');
+            }
             Event::Start(Tag::FootnoteDefinition(name)) => {
                 let number = footnote_numbers.len() + 1;
                 footnote_numbers.entry(name).or_insert(number);
@@ -153,10 +178,31 @@ fn render_item(
     Ok(())
 }
 
+/// The ID every header in a chapter gets on the rendered page, in order.
+fn header_ids(content: &str, opts: Options) -> Vec<String> {
+    let mut headers = Vec::new();
+    let mut header = None;
+
+    for event in Parser::new_ext(content, opts) {
+        match event {
+            Event::Start(Tag::Header(_)) => header = Some(String::new()),
+            Event::End(Tag::Header(_)) => headers.extend(header.take()),
+            Event::Text(text) => {
+                if let Some(ref mut header) = header {
+                    header.push_str(&text);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    utils::ids_for_headers(headers.iter().map(String::as_str))
+}
+
 /// Exports the index and search options to a JS script which stores the index in `window.search`.
 /// Using a JS script is a workaround for CORS in `file://` URIs. It also removes the need for
 /// downloading/parsing JSON in JS.
-fn write_to_js(index: Index, search_config: &Search) -> Result<String> {
+fn write_to_js(index: Index, search_config: &Search, minify: bool) -> Result<String> {
     use std::collections::BTreeMap;
     use self::elasticlunr::config::{SearchBool, SearchOptions, SearchOptionsField};
 
@@ -208,12 +254,34 @@ fn write_to_js(index: Index, search_config: &Search) -> Result<String> {
 
     // By converting to serde_json::Value as an intermediary, we use a
     // BTreeMap internally and can force a stable ordering of map keys.
-    let json_contents = serde_json::to_value(&json_contents)?;
+    let mut json_contents = serde_json::to_value(&json_contents)?;
+    if minify {
+        round_scores(&mut json_contents);
+    }
     let json_contents = serde_json::to_string(&json_contents)?;
 
     Ok(format!("window.search = {};", json_contents))
 }
 
+/// Shorten every fractional number in the index (the term frequencies, which
+/// are mostly square roots) to three decimal places. That is plenty for
+/// ranking results and saves a lot of space.
+fn round_scores(value: &mut serde_json::Value) {
+    use serde_json::{Number, Value};
+
+    match *value {
+        Value::Number(ref mut n) if n.is_f64() => {
+            let rounded = n.as_f64().map(|f| (f * 1000.0).round() / 1000.0);
+            if let Some(rounded) = rounded.and_then(Number::from_f64) {
+                *n = rounded;
+            }
+        }
+        Value::Array(ref mut values) => values.iter_mut().for_each(round_scores),
+        Value::Object(ref mut map) => map.values_mut().for_each(round_scores),
+        _ => {}
+    }
+}
+
 fn clean_html(html: &str) -> String {
     lazy_static! {
         static ref AMMONIA: ammonia::Builder<'static> = {
@@ -233,3 +301,24 @@ fn clean_html(html: &str) -> String {
     }
     AMMONIA.clean(html).to_string()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_ids_match_the_rendered_page() {
+        let content = "# Foo\n\n### Deeper\n\n## Bar {#foo}\n\n## Foo\n";
+        let mut opts = Options::empty();
+        opts.insert(OPTION_ENABLE_TABLES);
+        opts.insert(OPTION_ENABLE_FOOTNOTES);
+
+        let ids = header_ids(content, opts);
+        assert_eq!(ids, vec!["foo-1", "deeper", "foo", "foo-2"]);
+
+        let rendered = utils::render_markdown(content, false);
+        for id in &ids {
+            assert!(rendered.contains(&format!("id=\"{}\"", id)));
+        }
+    }
+}
@@ -0,0 +1,226 @@
+//! Turning the whole book into one self-contained HTML file, for
+//! `output.html.single-file = true`.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use base64;
+use regex::{Captures, Regex};
+
+use errors::*;
+use utils;
+
+/// The name of the generated file.
+pub const FILE_NAME: &str = "book.html";
+
+/// Font formats which aren't worth embedding because every browser able to
+/// show the page supports WOFF2.
+const LEGACY_FONTS: &[&str] = &["eot", "otf", "ttf", "woff"];
+
+/// Embed every file in `destination` the page refers to (stylesheets,
+/// scripts, images, ...) as a data URI, and turn links to the book's pages
+/// into links to the matching anchors in `page_anchors`.
+pub fn inline(
+    html: &str,
+    destination: &Path,
+    page_anchors: &HashMap<String, String>,
+) -> Result<String> {
+    lazy_static! {
+        static ref ATTR: Regex = Regex::new(r#"(\s(?:href|src)=")([^"]*)""#).unwrap();
+    }
+
+    replace_all(&ATTR, html, |caps| {
+        let url = &caps[2];
+        let replacement = match page_anchor(url, page_anchors) {
+            Some(anchor) => anchor,
+            None => match local_file(url, destination) {
+                Some(path) => data_uri(destination, &path)?,
+                None => url.to_string(),
+            },
+        };
+        Ok(format!("{}{}\"", &caps[1], replacement))
+    })
+}
+
+/// Where a link to one of the book's pages (e.g. `first/index.html#foo`)
+/// leads on the single page.
+fn page_anchor(url: &str, page_anchors: &HashMap<String, String>) -> Option<String> {
+    if !utils::is_relative_link(url) {
+        return None;
+    }
+
+    let mut parts = url.splitn(2, '#');
+    let page = parts.next().unwrap_or("");
+    let page = page.split('?').next().unwrap_or("");
+    let anchor = page_anchors.get(page.trim_start_matches("./"))?;
+
+    match parts.next() {
+        Some(fragment) if !fragment.is_empty() => Some(format!("#{}-{}", anchor, fragment)),
+        _ => Some(format!("#{}", anchor)),
+    }
+}
+
+/// The file a relative URL points to, relative to `destination`, if it
+/// exists.
+fn local_file(url: &str, destination: &Path) -> Option<String> {
+    if !utils::is_relative_link(url) {
+        return None;
+    }
+
+    let path = url.split(&['?', '#'][..]).next().unwrap_or("");
+    let path = utils::fs::resolve_link(Path::new(""), path)?;
+    if destination.join(&path).is_file() {
+        Some(utils::fs::normalize_path(&path.to_string_lossy()))
+    } else {
+        None
+    }
+}
+
+fn data_uri(destination: &Path, path: &str) -> Result<String> {
+    let file = destination.join(path);
+    let mut content = fs::read(&file).chain_err(|| format!("Unable to read {}", file.display()))?;
+
+    // Stylesheets refer to fonts and images of their own
+    if path.ends_with(".css") {
+        content = inline_css(&String::from_utf8_lossy(&content), destination, path)?.into_bytes();
+    }
+
+    // Text is mostly left alone, so fonts embedded in stylesheets don't end
+    // up base64 encoded twice
+    let mime_type = mime_type(path);
+    if is_text(mime_type) {
+        Ok(format!("data:{};charset=utf-8,{}", mime_type, percent_encode(&content)))
+    } else {
+        Ok(format!("data:{};base64,{}", mime_type, base64::encode(&content)))
+    }
+}
+
+/// Escape whatever can't appear as-is in a data URI inside an HTML attribute.
+fn percent_encode(content: &[u8]) -> String {
+    let mut encoded = String::with_capacity(content.len());
+
+    for &byte in content {
+        match byte {
+            b'%' | b'#' | b'"' | b'&' | b'<' | b'>' => {
+                encoded.push_str(&format!("%{:02X}", byte));
+            }
+            b' '..=b'~' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+
+    encoded
+}
+
+fn is_text(mime_type: &str) -> bool {
+    mime_type.starts_with("text/") || mime_type == "application/javascript"
+        || mime_type == "application/json" || mime_type == "image/svg+xml"
+}
+
+/// Embed the files a stylesheet at `path` refers to with `url(...)`.
+fn inline_css(css: &str, destination: &Path, path: &str) -> Result<String> {
+    lazy_static! {
+        static ref URL: Regex = Regex::new(r#"url\(\s*['"]?([^'")]+?)['"]?\s*\)"#).unwrap();
+    }
+
+    replace_all(&URL, css, |caps| {
+        let url = &caps[1];
+        let extension = Path::new(url.split(&['?', '#'][..]).next().unwrap_or(""))
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or("");
+        // SVG fonts are always referred to by their ID
+        let legacy_font = LEGACY_FONTS.contains(&extension) || extension == "svg" && url.contains('#');
+        if legacy_font || !utils::is_relative_link(url) {
+            return Ok(caps[0].to_string());
+        }
+
+        let relative = url.split(&['?', '#'][..]).next().unwrap_or("");
+        let target = utils::fs::resolve_link(Path::new(path), relative)
+            .map(|target| utils::fs::normalize_path(&target.to_string_lossy()));
+        match target {
+            Some(ref target) if destination.join(target).is_file() => {
+                Ok(format!("url(\"{}\")", data_uri(destination, target)?))
+            }
+            _ => Ok(caps[0].to_string()),
+        }
+    })
+}
+
+/// Like `Regex::replace_all()`, but the replacement can fail.
+fn replace_all<F>(re: &Regex, text: &str, mut replace: F) -> Result<String>
+where
+    F: FnMut(&Captures) -> Result<String>,
+{
+    let mut out = String::with_capacity(text.len());
+    let mut last = 0;
+
+    for caps in re.captures_iter(text) {
+        let whole = caps.get(0).unwrap();
+        out.push_str(&text[last..whole.start()]);
+        out.push_str(&replace(&caps)?);
+        last = whole.end();
+    }
+    out.push_str(&text[last..]);
+
+    Ok(out)
+}
+
+fn mime_type(path: &str) -> &'static str {
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+
+    match extension.as_str() {
+        "html" => "text/html",
+        "css" => "text/css",
+        "js" => "application/javascript",
+        "json" => "application/json",
+        "txt" => "text/plain",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "woff2" => "font/woff2",
+        "woff" => "font/woff",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "eot" => "application/vnd.ms-fontobject",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::Builder as TempFileBuilder;
+
+    #[test]
+    fn local_files_are_embedded_and_pages_become_anchors() {
+        let temp = TempFileBuilder::new().prefix("mdbook").tempdir().unwrap();
+        utils::fs::write_file(temp.path(), "img/dot.png", b"png").unwrap();
+        utils::fs::write_file(
+            temp.path(),
+            "css/style.css",
+            b"a { background: url('../img/dot.png'); } @font-face { src: url(font.eot); }",
+        ).unwrap();
+        let mut page_anchors = HashMap::new();
+        page_anchors.insert(String::from("first/index.html"), String::from("first-index"));
+
+        let html = r##"<link rel="stylesheet" href="css/style.css">
+<a href="first/index.html">first</a> <a href="first/index.html#foo">foo</a>
+<img src="img/dot.png"> <img src="missing.png"> <a href="https://example.com/">x</a>"##;
+        let should_be = r##"<link rel="stylesheet" href="data:text/css;charset=utf-8,a { background: url(%22data:image/png;base64,cG5n%22); } @font-face { src: url(font.eot); }">
+<a href="#first-index">first</a> <a href="#first-index-foo">foo</a>
+<img src="data:image/png;base64,cG5n"> <img src="missing.png"> <a href="https://example.com/">x</a>"##;
+
+        let got = inline(html, temp.path(), &page_anchors).unwrap();
+
+        assert_eq!(got, should_be);
+    }
+}
@@ -0,0 +1,121 @@
+//! Picking which template each chapter is rendered with, for the
+//! `[output.html.templates]` table.
+
+use std::path::Path;
+
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+
+use config::HtmlConfig;
+use errors::*;
+use theme::Theme;
+use utils;
+
+/// The template chapters are rendered with unless configured otherwise.
+pub const DEFAULT: &str = "index";
+
+/// The name a template from the theme's `templates/` directory is registered
+/// under, so it can't clash with `index` or the partials.
+pub fn registered_name(name: &str) -> String {
+    format!("templates/{}", name)
+}
+
+/// The chapters each of the theme's extra page templates is used for.
+#[derive(Debug, Default)]
+pub struct PageTemplates {
+    templates: Vec<(String, GlobSet)>,
+}
+
+impl PageTemplates {
+    pub fn new(html_config: &HtmlConfig, theme: &Theme) -> Result<PageTemplates> {
+        let mut templates = Vec::new();
+
+        for (name, patterns) in &html_config.templates {
+            if !theme.templates.contains_key(name) {
+                bail!(
+                    "The \"{}\" template in `output.html.templates` doesn't exist, it should be \
+                     in the theme's templates/{}.hbs",
+                    name,
+                    name
+                );
+            }
+
+            let mut globs = GlobSetBuilder::new();
+            for pattern in patterns {
+                let glob = GlobBuilder::new(pattern)
+                    .literal_separator(true)
+                    .build()
+                    .chain_err(|| {
+                        format!("Invalid pattern \"{}\" for the {} template", pattern, name)
+                    })?;
+                globs.add(glob);
+            }
+            let globs = globs
+                .build()
+                .chain_err(|| format!("Unable to use the patterns for the {} template", name))?;
+
+            templates.push((name.clone(), globs));
+        }
+
+        Ok(PageTemplates { templates })
+    }
+
+    /// The registered name of the template a chapter should be rendered with.
+    /// It is an error for a chapter to match more than one template.
+    pub fn for_chapter(&self, path: &Path) -> Result<String> {
+        let path = utils::fs::normalize_path(&path.to_string_lossy());
+        let mut matching = self.templates
+            .iter()
+            .filter(|(_, globs)| globs.is_match(&path))
+            .map(|(name, _)| name.as_str());
+
+        match (matching.next(), matching.next()) {
+            (None, _) => Ok(DEFAULT.to_string()),
+            (Some(name), None) => Ok(registered_name(name)),
+            (Some(first), Some(second)) => bail!(
+                "{} matches both the {} and {} templates in `output.html.templates`",
+                path,
+                first,
+                second
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chapters_get_the_template_they_match() {
+        let mut theme = Theme::default();
+        theme.templates.insert("wide".to_string(), Vec::new());
+        theme.templates.insert("landing".to_string(), Vec::new());
+
+        let mut html_config = HtmlConfig::default();
+        html_config.templates.insert(
+            "wide".to_string(),
+            vec!["appendix/*.md".to_string(), "reference.md".to_string()],
+        );
+        html_config
+            .templates
+            .insert("landing".to_string(), vec!["**/index.md".to_string()]);
+        let templates = PageTemplates::new(&html_config, &theme).unwrap();
+
+        let template = |path: &str| templates.for_chapter(Path::new(path));
+        assert_eq!(template("reference.md").unwrap(), "templates/wide");
+        assert_eq!(template("appendix/glossary.md").unwrap(), "templates/wide");
+        assert_eq!(template("appendix/deep/glossary.md").unwrap(), "index");
+        assert_eq!(template("guide/index.md").unwrap(), "templates/landing");
+        assert!(template("appendix/index.md").is_err());
+    }
+
+    #[test]
+    fn templates_must_exist() {
+        let mut html_config = HtmlConfig::default();
+        html_config
+            .templates
+            .insert("missing".to_string(), vec!["*.md".to_string()]);
+
+        assert!(PageTemplates::new(&html_config, &Theme::default()).is_err());
+    }
+}
@@ -0,0 +1,142 @@
+//! The book's chapters as a nested tree, so themes can build their own
+//! navigation (breadcrumbs, landing pages for parts, ...) instead of working
+//! from the flat `chapters` list the `toc` helper uses.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use serde_json::{self, Value};
+
+use book::{BookItem, Chapter};
+use errors::*;
+use utils;
+use utils::git::LastModified;
+
+/// Turn the book's items into the `chapter_tree` template data. Each chapter
+/// looks like this (`last_modified` is only there when it is known):
+///
+/// ```json
+/// {
+///   "type": "chapter",
+///   "name": "Configuration",
+///   "number": "3.2.",
+///   "level": 2,
+///   "path": "format/config.md",
+///   "link": "format/config.html",
+///   "parent_names": ["Format"],
+///   "draft": false,
+///   "last_modified": { "timestamp": 1527422592, "datetime": "...", ... },
+///   "sub_items": []
+/// }
+/// ```
+///
+/// while separators are just `{ "type": "separator" }`.
+pub fn chapter_tree(
+    items: &[BookItem],
+    last_modified: &BTreeMap<PathBuf, LastModified>,
+) -> Result<Vec<Value>> {
+    items
+        .iter()
+        .map(|item| match *item {
+            BookItem::Chapter(ref ch) => chapter(ch, last_modified),
+            BookItem::Separator => Ok(json!({ "type": "separator" })),
+        })
+        .collect()
+}
+
+fn chapter(ch: &Chapter, last_modified: &BTreeMap<PathBuf, LastModified>) -> Result<Value> {
+    let path = ch.path
+        .to_str()
+        .chain_err(|| "Could not convert path to str")?;
+    let path = utils::fs::normalize_path(path);
+    let draft = path.is_empty();
+    let link = if draft {
+        String::new()
+    } else {
+        utils::fs::normalize_path(&ch.path.with_extension("html").to_string_lossy())
+    };
+
+    let mut node = serde_json::Map::new();
+    node.insert("type".to_owned(), json!("chapter"));
+    node.insert("name".to_owned(), json!(ch.name));
+    node.insert(
+        "number".to_owned(),
+        json!(ch.number.as_ref().map(|number| number.to_string())),
+    );
+    node.insert(
+        "level".to_owned(),
+        json!(ch.number.as_ref().map_or(0, |number| number.len())),
+    );
+    node.insert("path".to_owned(), json!(path));
+    node.insert("link".to_owned(), json!(link));
+    node.insert("parent_names".to_owned(), json!(ch.parent_names));
+    node.insert("draft".to_owned(), json!(draft));
+    if let Some(modified) = last_modified.get(&ch.path) {
+        node.insert("last_modified".to_owned(), json!(modified));
+    }
+    node.insert(
+        "sub_items".to_owned(),
+        json!(chapter_tree(&ch.sub_items, last_modified)?),
+    );
+
+    Ok(Value::Object(node))
+}
+
+/// The chapters leading to the one at `path` (e.g. `format/config.md`), from
+/// the top of the tree down to the chapter itself. Empty if no chapter has
+/// that path.
+pub fn lineage<'a>(tree: &'a [Value], path: &str) -> Vec<&'a Value> {
+    for node in tree {
+        if node["path"] == *path {
+            return vec![node];
+        }
+
+        if let Some(sub_items) = node["sub_items"].as_array() {
+            let mut found = lineage(sub_items, path);
+            if !found.is_empty() {
+                found.insert(0, node);
+                return found;
+            }
+        }
+    }
+
+    Vec::new()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use book::SectionNumber;
+
+    #[test]
+    fn chapters_keep_their_place_in_the_book() {
+        let mut config = Chapter::new("Config", String::new(), "format/config.md", vec![
+            "Format".to_string(),
+        ]);
+        config.number = Some(SectionNumber(vec![1, 1]));
+        let mut format = Chapter::new("Format", String::new(), "format/index.md", vec![]);
+        format.number = Some(SectionNumber(vec![1]));
+        format.sub_items.push(BookItem::Chapter(config));
+        let items = vec![
+            BookItem::Chapter(format),
+            BookItem::Separator,
+            BookItem::Chapter(Chapter::new("Draft", String::new(), "", vec![])),
+        ];
+
+        let tree = chapter_tree(&items, &BTreeMap::new()).unwrap();
+
+        assert_eq!(tree[1], json!({ "type": "separator" }));
+        let config = &tree[0]["sub_items"][0];
+        assert_eq!(config["number"], json!("1.1."));
+        assert_eq!(config["level"], json!(2));
+        assert_eq!(config["link"], json!("format/config.html"));
+        assert_eq!(config["parent_names"], json!(["Format"]));
+        assert_eq!(tree[2]["number"], Value::Null);
+        assert_eq!(tree[2]["draft"], json!(true));
+
+        let found = lineage(&tree, "format/config.md");
+        let names = found.iter().map(|node| &node["name"]).collect::<Vec<_>>();
+        assert_eq!(names, vec!["Format", "Config"]);
+        assert!(lineage(&tree, "missing.md").is_empty());
+    }
+}
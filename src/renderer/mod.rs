@@ -15,6 +15,7 @@ pub use self::html_handlebars::HtmlHandlebars;
 
 mod html_handlebars;
 
+use std::collections::BTreeMap;
 use std::fs;
 use std::io::{self, Read};
 use std::path::PathBuf;
@@ -25,6 +26,7 @@ use shlex::Shlex;
 use errors::*;
 use config::Config;
 use book::Book;
+use utils::git::LastModified;
 
 const MDBOOK_VERSION: &str = env!("CARGO_PKG_VERSION");
 
@@ -66,6 +68,10 @@ pub struct RenderContext {
     /// renderers to cache intermediate results, this directory is not
     /// guaranteed to be empty or even exist.
     pub destination: PathBuf,
+    /// When each chapter (keyed by its path) was last changed, according to
+    /// git. This is empty if the book isn't in a git repository.
+    #[serde(default)]
+    pub last_modified: BTreeMap<PathBuf, LastModified>,
 }
 
 impl RenderContext {
@@ -81,6 +87,7 @@ impl RenderContext {
             version: MDBOOK_VERSION.to_string(),
             root: root.into(),
             destination: destination.into(),
+            last_modified: BTreeMap::new(),
         }
     }
 
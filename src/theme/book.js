@@ -18,14 +18,6 @@ function playpen_text(playpen) {
 (function codeSnippets() {
     // Hide Rust code lines prepended with a specific character
     var hiding_character = "#";
-    var request = fetch("https://play.rust-lang.org/meta/crates", {
-        headers: {
-            'Content-Type': "application/json",
-        },
-        method: 'POST',
-        mode: 'cors',
-    });
-
     function handle_crate_list_update(playpen_block, playground_crates) {
         // update the play buttons after receiving the response
         update_play_button(playpen_block, playground_crates);
@@ -114,26 +106,29 @@ function playpen_text(playpen) {
             .catch(function (error) { result_block.innerText = "Playground communication" + error.message; });
     }
 
-    // Syntax highlighting Configuration
-    hljs.configure({
-        tabReplace: '    ', // 4 spaces
-        languages: [],      // Languages used for auto-detection
-    });
-
-    if (window.ace) {
-        // language-rust class needs to be removed for editable
-        // blocks or highlightjs will capture events
-        Array
-            .from(document.querySelectorAll('code.editable'))
-            .forEach(function (block) { block.classList.remove('language-rust'); });
+    // Syntax highlighting Configuration, unless the code was already
+    // highlighted when the book was rendered
+    if (window.hljs) {
+        hljs.configure({
+            tabReplace: '    ', // 4 spaces
+            languages: [],      // Languages used for auto-detection
+        });
 
-        Array
-            .from(document.querySelectorAll('code:not(.editable)'))
-            .forEach(function (block) { hljs.highlightBlock(block); });
-    } else {
-        Array
-            .from(document.querySelectorAll('code'))
-            .forEach(function (block) { hljs.highlightBlock(block); });
+        if (window.ace) {
+            // language-rust class needs to be removed for editable
+            // blocks or highlightjs will capture events
+            Array
+                .from(document.querySelectorAll('code.editable'))
+                .forEach(function (block) { block.classList.remove('language-rust'); });
+
+            Array
+                .from(document.querySelectorAll('code:not(.editable)'))
+                .forEach(function (block) { hljs.highlightBlock(block); });
+        } else {
+            Array
+                .from(document.querySelectorAll('code'))
+                .forEach(function (block) { hljs.highlightBlock(block); });
+        }
     }
 
     // Adding the hljs class gives code blocks the color css
@@ -275,15 +270,24 @@ function playpen_text(playpen) {
         }
     });
 
-    request
-        .then(function (response) { return response.json(); })
-        .then(function (response) {
-            // get list of crates available in the rust playground
-            let playground_crates = response.crates.map(function (item) { return item["id"]; });
-            Array.from(document.querySelectorAll(".playpen")).forEach(function (block) {
-                handle_crate_list_update(block, playground_crates);
+    // Only ask the playground which crates it has if there's code to run
+    if (document.querySelector(".playpen")) {
+        fetch("https://play.rust-lang.org/meta/crates", {
+            headers: {
+                'Content-Type': "application/json",
+            },
+            method: 'POST',
+            mode: 'cors',
+        })
+            .then(function (response) { return response.json(); })
+            .then(function (response) {
+                // get list of crates available in the rust playground
+                let playground_crates = response.crates.map(function (item) { return item["id"]; });
+                Array.from(document.querySelectorAll(".playpen")).forEach(function (block) {
+                    handle_crate_list_update(block, playground_crates);
+                });
             });
-        });
+    }
 
 })();
 
@@ -293,15 +297,40 @@ function playpen_text(playpen) {
     var themePopup = document.getElementById('theme-list');
     var themeColorMetaTag = document.querySelector('meta[name="theme-color"]');
     var stylesheets = {
-        ayuHighlight: document.querySelector("[href='ayu-highlight.css']"),
-        tomorrowNight: document.querySelector("[href='tomorrow-night.css']"),
-        highlight: document.querySelector("[href='highlight.css']"),
+        ayuHighlight: document.querySelector("#ayu-highlight-css, [href$='ayu-highlight.css']"),
+        tomorrowNight: document.querySelector("#tomorrow-night-css, [href$='tomorrow-night.css']"),
+        highlight: document.querySelector("#highlight-css, [href$='/highlight.css'], [href='highlight.css']"),
     };
+    // Custom templates from before the default theme could be configured
+    // don't define it
+    var defaultTheme = typeof default_theme === 'undefined' ? 'light' : default_theme;
+
+    // The theme's button in the menu, if there is one
+    function themeButton(theme) {
+        var button = document.getElementById(theme);
+        return button !== null && themePopup.contains(button) ? button : null;
+    }
+
+    // Which code highlighting goes with a theme. Each button says so, apart
+    // from those in custom templates from before there were custom themes.
+    function themeHighlight(theme, button) {
+        var highlight = button === null ? null : button.getAttribute('data-highlight');
+        if (highlight) {
+            return highlight;
+        } else if (theme === 'coal' || theme === 'navy') {
+            return 'tomorrow-night';
+        } else if (theme === 'ayu') {
+            return 'ayu';
+        } else {
+            return 'highlight';
+        }
+    }
 
     function showThemes() {
         themePopup.style.display = 'block';
         themeToggleButton.setAttribute('aria-expanded', true);
-        themePopup.querySelector("button#" + document.body.className).focus();
+        var button = themeButton(document.body.className);
+        if (button !== null) { button.focus(); }
     }
 
     function hideThemes() {
@@ -310,28 +339,20 @@ function playpen_text(playpen) {
         themeToggleButton.focus();
     }
 
-    function set_theme(theme) {
-        let ace_theme;
-
-        if (theme == 'coal' || theme == 'navy') {
-            stylesheets.ayuHighlight.disabled = true;
-            stylesheets.tomorrowNight.disabled = false;
-            stylesheets.highlight.disabled = true;
+    function set_theme(theme, store) {
+        var button = themeButton(theme);
+        if (button === null && theme !== defaultTheme) {
+            // The theme isn't in this book (anymore)
+            theme = defaultTheme;
+            button = themeButton(theme);
+        }
+        var highlight = themeHighlight(theme, button);
 
-            ace_theme = "ace/theme/tomorrow_night";
-        } else if (theme == 'ayu') {
-            stylesheets.ayuHighlight.disabled = false;
-            stylesheets.tomorrowNight.disabled = true;
-            stylesheets.highlight.disabled = true;
+        stylesheets.ayuHighlight.disabled = highlight !== 'ayu';
+        stylesheets.tomorrowNight.disabled = highlight !== 'tomorrow-night';
+        stylesheets.highlight.disabled = highlight !== 'highlight';
 
-            ace_theme = "ace/theme/tomorrow_night";
-        } else {
-            stylesheets.ayuHighlight.disabled = true;
-            stylesheets.tomorrowNight.disabled = true;
-            stylesheets.highlight.disabled = false;
-
-            ace_theme = "ace/theme/dawn";
-        }
+        var ace_theme = highlight === 'highlight' ? "ace/theme/dawn" : "ace/theme/tomorrow_night";
 
         setTimeout(function () {
             themeColorMetaTag.content = getComputedStyle(document.body).backgroundColor;
@@ -345,9 +366,13 @@ function playpen_text(playpen) {
 
         var previousTheme;
         try { previousTheme = localStorage.getItem('mdbook-theme'); } catch (e) { }
-        if (previousTheme === null || previousTheme === undefined) { previousTheme = 'light'; }
+        if (previousTheme === null || previousTheme === undefined) { previousTheme = defaultTheme; }
 
-        try { localStorage.setItem('mdbook-theme', theme); } catch (e) { }
+        // Only remember themes readers picked, so the default keeps following
+        // their system's preference
+        if (store !== false) {
+            try { localStorage.setItem('mdbook-theme', theme); } catch (e) { }
+        }
 
         document.body.className = theme;
         html.classList.remove(previousTheme);
@@ -357,9 +382,9 @@ function playpen_text(playpen) {
     // Set theme
     var theme;
     try { theme = localStorage.getItem('mdbook-theme'); } catch(e) { }
-    if (theme === null || theme === undefined) { theme = 'light'; }
+    if (theme === null || theme === undefined) { theme = defaultTheme; }
 
-    set_theme(theme);
+    set_theme(theme, false);
 
     themeToggleButton.addEventListener('click', function () {
         if (themePopup.style.display === 'block') {
@@ -492,6 +517,38 @@ function playpen_text(playpen) {
         }
     }, { passive: true });
 
+    // Collapsible sections, which stay the way the reader left them
+    var sectionToggles = sidebar.querySelectorAll('.toggle');
+    var sectionStates = {};
+    try { sectionStates = JSON.parse(localStorage.getItem('mdbook-sidebar-sections')) || {}; } catch (e) { }
+
+    function setSectionExpanded(toggle, expanded) {
+        var item = toggle.parentElement;
+        item.classList.toggle('expanded', expanded);
+        item.classList.toggle('collapsed', !expanded);
+        toggle.setAttribute('aria-expanded', expanded);
+    }
+
+    Array.from(sectionToggles).forEach(function (toggle) {
+        var section = toggle.getAttribute('data-section');
+        var item = toggle.parentElement;
+        var subSections = item.nextElementSibling;
+
+        // The chapters leading to the current one are never collapsed
+        var leadsToCurrent = item.querySelector('.active') !== null
+            || (subSections !== null && subSections.querySelector('.active') !== null);
+        if (sectionStates.hasOwnProperty(section) && !leadsToCurrent) {
+            setSectionExpanded(toggle, sectionStates[section]);
+        }
+
+        toggle.addEventListener('click', function () {
+            var expanded = item.classList.contains('collapsed');
+            setSectionExpanded(toggle, expanded);
+            sectionStates[section] = expanded;
+            try { localStorage.setItem('mdbook-sidebar-sections', JSON.stringify(sectionStates)); } catch (e) { }
+        });
+    });
+
     // Scroll sidebar to current active section
     var activeSection = sidebar.querySelector(".active");
     if (activeSection) {
@@ -0,0 +1,60 @@
+//! Fonts used by the default theme, for books which shouldn't load them from
+//! Google Fonts (i.e. `output.html.offline = true`).
+
+pub static CSS: &[u8] = include_bytes!("fonts.css");
+
+/// Every font file, as `(path in the book, contents)` pairs.
+pub static FILES: &[(&str, &[u8])] = &[
+    (
+        "fonts/open-sans-v17-all-charsets-300.woff2",
+        include_bytes!("open-sans-v17-all-charsets-300.woff2"),
+    ),
+    (
+        "fonts/open-sans-v17-all-charsets-300italic.woff2",
+        include_bytes!("open-sans-v17-all-charsets-300italic.woff2"),
+    ),
+    (
+        "fonts/open-sans-v17-all-charsets-regular.woff2",
+        include_bytes!("open-sans-v17-all-charsets-regular.woff2"),
+    ),
+    (
+        "fonts/open-sans-v17-all-charsets-italic.woff2",
+        include_bytes!("open-sans-v17-all-charsets-italic.woff2"),
+    ),
+    (
+        "fonts/open-sans-v17-all-charsets-600.woff2",
+        include_bytes!("open-sans-v17-all-charsets-600.woff2"),
+    ),
+    (
+        "fonts/open-sans-v17-all-charsets-600italic.woff2",
+        include_bytes!("open-sans-v17-all-charsets-600italic.woff2"),
+    ),
+    (
+        "fonts/open-sans-v17-all-charsets-700.woff2",
+        include_bytes!("open-sans-v17-all-charsets-700.woff2"),
+    ),
+    (
+        "fonts/open-sans-v17-all-charsets-700italic.woff2",
+        include_bytes!("open-sans-v17-all-charsets-700italic.woff2"),
+    ),
+    (
+        "fonts/open-sans-v17-all-charsets-800.woff2",
+        include_bytes!("open-sans-v17-all-charsets-800.woff2"),
+    ),
+    (
+        "fonts/open-sans-v17-all-charsets-800italic.woff2",
+        include_bytes!("open-sans-v17-all-charsets-800italic.woff2"),
+    ),
+    (
+        "fonts/source-code-pro-v11-all-charsets-500.woff2",
+        include_bytes!("source-code-pro-v11-all-charsets-500.woff2"),
+    ),
+    (
+        "fonts/OPEN-SANS-LICENSE.txt",
+        include_bytes!("OPEN-SANS-LICENSE.txt"),
+    ),
+    (
+        "fonts/SOURCE-CODE-PRO-LICENSE.txt",
+        include_bytes!("SOURCE-CODE-PRO-LICENSE.txt"),
+    ),
+];
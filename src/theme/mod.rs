@@ -1,18 +1,22 @@
 #![allow(missing_docs)]
 
+pub mod fonts;
 pub mod playpen_editor;
 
 #[cfg(feature = "search")]
 pub mod searcher;
 
-use std::path::Path;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 use std::fs::File;
 use std::io::Read;
 
 use errors::*;
+use utils;
 
 pub static INDEX: &'static [u8] = include_bytes!("index.hbs");
 pub static HEADER: &'static [u8] = include_bytes!("header.hbs");
+pub static REDIRECT: &[u8] = include_bytes!("redirect.hbs");
 pub static CSS: &'static [u8] = include_bytes!("book.css");
 pub static FAVICON: &'static [u8] = include_bytes!("favicon.png");
 pub static JS: &'static [u8] = include_bytes!("book.js");
@@ -21,6 +25,7 @@ pub static TOMORROW_NIGHT_CSS: &'static [u8] = include_bytes!("tomorrow-night.cs
 pub static HIGHLIGHT_CSS: &'static [u8] = include_bytes!("highlight.css");
 pub static AYU_HIGHLIGHT_CSS: &'static [u8] = include_bytes!("ayu-highlight.css");
 pub static CLIPBOARD_JS: &'static [u8] = include_bytes!("clipboard.min.js");
+pub static SERVICE_WORKER: &[u8] = include_bytes!("sw.js");
 pub static FONT_AWESOME: &'static [u8] = include_bytes!("FontAwesome/css/font-awesome.min.css");
 pub static FONT_AWESOME_EOT: &'static [u8] =
     include_bytes!("FontAwesome/fonts/fontawesome-webfont.eot");
@@ -34,6 +39,103 @@ pub static FONT_AWESOME_WOFF2: &'static [u8] =
     include_bytes!("FontAwesome/fonts/fontawesome-webfont.woff2");
 pub static FONT_AWESOME_OTF: &'static [u8] = include_bytes!("FontAwesome/fonts/FontAwesome.otf");
 
+/// A theme built into mdBook, which `output.html.theme` and
+/// `output.html.parent-theme` can name instead of pointing at a directory.
+/// Its files are layered over the default theme like a theme directory's.
+#[derive(Debug, PartialEq)]
+pub struct BuiltinTheme {
+    pub name: &'static str,
+    /// The theme's files, by their path in a theme directory (e.g.
+    /// `blocks.hbs` or `partials/footer.hbs`).
+    pub files: &'static [(&'static str, &'static [u8])],
+}
+
+/// The themes built into mdBook.
+pub static BUILTIN_THEMES: &[BuiltinTheme] = &[
+    // Nothing but the default files
+    BuiltinTheme {
+        name: "default",
+        files: &[],
+    },
+    BuiltinTheme {
+        name: "wide",
+        files: &[("blocks.hbs", include_bytes!("packages/wide/blocks.hbs"))],
+    },
+];
+
+/// Look up one of the `BUILTIN_THEMES` by name.
+pub fn builtin_theme(name: &str) -> Option<&'static BuiltinTheme> {
+    BUILTIN_THEMES.iter().find(|theme| theme.name == name)
+}
+
+/// One of the layers a `Theme` is put together from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ThemeLayer {
+    /// A theme directory. It is skipped if it doesn't exist.
+    Dir(PathBuf),
+    /// One of the themes built into mdBook.
+    Builtin(&'static BuiltinTheme),
+}
+
+impl ThemeLayer {
+    /// Read one of the layer's files, by its path in a theme directory.
+    fn file(&self, name: &str) -> Option<Vec<u8>> {
+        match *self {
+            ThemeLayer::Dir(ref dir) => {
+                let filename = dir.join(name);
+                if !filename.exists() {
+                    return None;
+                }
+
+                let mut content = Vec::new();
+                match load_file_contents(&filename, &mut content) {
+                    Ok(_) => Some(content),
+                    Err(e) => {
+                        warn!("Couldn't load custom file, {}: {}", filename.display(), e);
+                        None
+                    }
+                }
+            }
+            ThemeLayer::Builtin(theme) => theme
+                .files
+                .iter()
+                .find(|&&(file, _)| file == name)
+                .map(|&(_, content)| content.to_vec()),
+        }
+    }
+
+    /// Every `.hbs` file in one of the layer's sub-directories, named after
+    /// its path in there without the extension.
+    fn templates(&self, dir: &str) -> BTreeMap<String, Vec<u8>> {
+        match *self {
+            ThemeLayer::Dir(ref theme_dir) => load_templates(&theme_dir.join(dir)),
+            ThemeLayer::Builtin(theme) => {
+                let prefix = format!("{}/", dir);
+                theme
+                    .files
+                    .iter()
+                    .filter(|&&(file, _)| file.starts_with(&prefix) && file.ends_with(".hbs"))
+                    .map(|&(file, content)| {
+                        let name = &file[prefix.len()..file.len() - ".hbs".len()];
+                        (name.to_string(), content.to_vec())
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+impl<'a> From<&'a Path> for ThemeLayer {
+    fn from(dir: &'a Path) -> ThemeLayer {
+        ThemeLayer::Dir(dir.to_path_buf())
+    }
+}
+
+impl From<PathBuf> for ThemeLayer {
+    fn from(dir: PathBuf) -> ThemeLayer {
+        ThemeLayer::Dir(dir)
+    }
+}
 
 /// The `Theme` struct should be used instead of the static variables because
 /// the `new()` method will look if the user has a theme directory in their
@@ -45,6 +147,7 @@ pub static FONT_AWESOME_OTF: &'static [u8] = include_bytes!("FontAwesome/fonts/F
 pub struct Theme {
     pub index: Vec<u8>,
     pub header: Vec<u8>,
+    pub redirect: Vec<u8>,
     pub css: Vec<u8>,
     pub favicon: Vec<u8>,
     pub js: Vec<u8>,
@@ -53,47 +156,81 @@ pub struct Theme {
     pub ayu_highlight_css: Vec<u8>,
     pub highlight_js: Vec<u8>,
     pub clipboard_js: Vec<u8>,
+    pub service_worker: Vec<u8>,
+    pub fonts_css: Vec<u8>,
+    /// Extra partials from `partials/`, named after their path without the
+    /// `.hbs` extension (e.g. `nav/footer` for `partials/nav/footer.hbs`).
+    pub partials: BTreeMap<String, Vec<u8>>,
+    /// Extra page templates from `templates/`, which chapters can be
+    /// rendered with instead of `index.hbs`. Named like the partials.
+    pub templates: BTreeMap<String, Vec<u8>>,
+    /// The `{{#*inline "..."}}` block overrides from each layer's
+    /// `blocks.hbs`, which are put in front of every page template.
+    pub blocks: Vec<u8>,
 }
 
 impl Theme {
     /// Creates a `Theme` from the given `theme_dir`.
     /// If a file is found in the theme dir, it will override the default version.
     pub fn new<P: AsRef<Path>>(theme_dir: P) -> Self {
-        let theme_dir = theme_dir.as_ref();
+        Theme::layered(&[theme_dir.as_ref()])
+    }
+
+    /// Creates a `Theme` by laying each of `layers` (usually theme
+    /// directories) over the default theme in turn, so a file in a later
+    /// layer overrides the same file in an earlier one. Partials and page
+    /// templates are merged by name, and the `blocks.hbs` of every layer is
+    /// kept, in order.
+    pub fn layered<L: Clone + Into<ThemeLayer>>(layers: &[L]) -> Self {
         let mut theme = Theme::default();
 
+        for layer in layers {
+            theme.add_layer(&layer.clone().into());
+        }
+
+        theme
+    }
+
+    fn add_layer(&mut self, layer: &ThemeLayer) {
         // If the theme directory doesn't exist there's no point continuing...
-        if !theme_dir.exists() || !theme_dir.is_dir() {
-            return theme;
+        if let ThemeLayer::Dir(ref theme_dir) = *layer {
+            if !theme_dir.exists() || !theme_dir.is_dir() {
+                return;
+            }
         }
 
         // Check for individual files, if they exist copy them across
         {
             let files = vec![
-                (theme_dir.join("index.hbs"), &mut theme.index),
-                (theme_dir.join("header.hbs"), &mut theme.header),
-                (theme_dir.join("book.js"), &mut theme.js),
-                (theme_dir.join("book.css"), &mut theme.css),
-                (theme_dir.join("favicon.png"), &mut theme.favicon),
-                (theme_dir.join("highlight.js"), &mut theme.highlight_js),
-                (theme_dir.join("clipboard.min.js"), &mut theme.clipboard_js),
-                (theme_dir.join("highlight.css"), &mut theme.highlight_css),
-                (theme_dir.join("tomorrow-night.css"), &mut theme.tomorrow_night_css),
-                (theme_dir.join("ayu-highlight.css"), &mut theme.ayu_highlight_css),
+                ("index.hbs", &mut self.index),
+                ("header.hbs", &mut self.header),
+                ("redirect.hbs", &mut self.redirect),
+                ("book.js", &mut self.js),
+                ("book.css", &mut self.css),
+                ("favicon.png", &mut self.favicon),
+                ("highlight.js", &mut self.highlight_js),
+                ("clipboard.min.js", &mut self.clipboard_js),
+                ("highlight.css", &mut self.highlight_css),
+                ("tomorrow-night.css", &mut self.tomorrow_night_css),
+                ("ayu-highlight.css", &mut self.ayu_highlight_css),
+                ("sw.js", &mut self.service_worker),
+                ("fonts/fonts.css", &mut self.fonts_css),
             ];
 
             for (filename, dest) in files {
-                if !filename.exists() {
-                    continue;
-                }
-
-                if let Err(e) = load_file_contents(&filename, dest) {
-                    warn!("Couldn't load custom file, {}: {}", filename.display(), e);
+                if let Some(content) = layer.file(filename) {
+                    *dest = content;
                 }
             }
         }
 
-        theme
+        self.partials.extend(layer.templates("partials"));
+        self.templates.extend(layer.templates("templates"));
+
+        if let Some(blocks) = layer.file("blocks.hbs") {
+            self.blocks.extend(blocks);
+            self.blocks.push(b'\n');
+        }
     }
 }
 
@@ -102,6 +239,7 @@ impl Default for Theme {
         Theme {
             index: INDEX.to_owned(),
             header: HEADER.to_owned(),
+            redirect: REDIRECT.to_owned(),
             css: CSS.to_owned(),
             favicon: FAVICON.to_owned(),
             js: JS.to_owned(),
@@ -110,10 +248,49 @@ impl Default for Theme {
             ayu_highlight_css: AYU_HIGHLIGHT_CSS.to_owned(),
             highlight_js: HIGHLIGHT_JS.to_owned(),
             clipboard_js: CLIPBOARD_JS.to_owned(),
+            service_worker: SERVICE_WORKER.to_owned(),
+            fonts_css: fonts::CSS.to_owned(),
+            partials: BTreeMap::new(),
+            templates: BTreeMap::new(),
+            blocks: Vec::new(),
         }
     }
 }
 
+/// Load every `.hbs` file in a directory and its sub-directories.
+fn load_templates(dir: &Path) -> BTreeMap<String, Vec<u8>> {
+    let mut templates = BTreeMap::new();
+    if !dir.is_dir() {
+        return templates;
+    }
+
+    let files = match utils::fs::visible_files(dir) {
+        Ok(files) => files,
+        Err(e) => {
+            warn!("Couldn't read the templates in {}: {}", dir.display(), e);
+            return templates;
+        }
+    };
+
+    for file in files {
+        if !file.ends_with(".hbs") {
+            continue;
+        }
+
+        let mut content = Vec::new();
+        let filename = dir.join(&file);
+        if let Err(e) = load_file_contents(&filename, &mut content) {
+            warn!("Couldn't load custom file, {}: {}", filename.display(), e);
+            continue;
+        }
+
+        let name = &file[..file.len() - ".hbs".len()];
+        templates.insert(name.to_string(), content);
+    }
+
+    templates
+}
+
 /// Checks if a file exists, if so, the destination buffer will be filled with
 /// its contents.
 fn load_file_contents<P: AsRef<Path>>(filename: P, dest: &mut Vec<u8>) -> Result<()> {
@@ -135,6 +312,7 @@ fn load_file_contents<P: AsRef<Path>>(filename: P, dest: &mut Vec<u8>) -> Result
 mod tests {
     use super::*;
     use tempfile::Builder as TempFileBuilder;
+    use std::fs;
     use std::path::PathBuf;
 
     #[test]
@@ -166,12 +344,15 @@ mod tests {
             let filename = temp.path().join(special_file.file_name().unwrap());
             let _ = File::create(&filename);
         }
+        fs::create_dir(temp.path().join("fonts")).unwrap();
+        File::create(temp.path().join("fonts").join("fonts.css")).unwrap();
 
         let got = Theme::new(temp.path());
 
         let empty = Theme {
             index: Vec::new(),
             header: Vec::new(),
+            redirect: Vec::new(),
             css: Vec::new(),
             favicon: Vec::new(),
             js: Vec::new(),
@@ -180,8 +361,74 @@ mod tests {
             ayu_highlight_css: Vec::new(),
             highlight_js: Vec::new(),
             clipboard_js: Vec::new(),
+            service_worker: Vec::new(),
+            fonts_css: Vec::new(),
+            partials: BTreeMap::new(),
+            templates: BTreeMap::new(),
+            blocks: Vec::new(),
         };
 
         assert_eq!(got, empty);
     }
+
+    #[test]
+    fn partials_and_templates_are_named_after_their_path() {
+        let temp = TempFileBuilder::new().prefix("mdbook").tempdir().unwrap();
+        utils::fs::write_file(temp.path(), "partials/footer.hbs", b"footer").unwrap();
+        utils::fs::write_file(temp.path(), "partials/nav/crumbs.hbs", b"crumbs").unwrap();
+        utils::fs::write_file(temp.path(), "partials/notes.txt", b"").unwrap();
+        utils::fs::write_file(temp.path(), "templates/wide.hbs", b"wide").unwrap();
+
+        let got = Theme::new(temp.path());
+
+        let partials = got.partials.keys().map(String::as_str).collect::<Vec<_>>();
+        assert_eq!(partials, vec!["footer", "nav/crumbs"]);
+        assert_eq!(got.partials["nav/crumbs"], b"crumbs");
+        assert_eq!(got.templates["wide"], b"wide");
+    }
+
+    #[test]
+    fn later_layers_override_earlier_ones_file_by_file() {
+        let base = TempFileBuilder::new().prefix("mdbook").tempdir().unwrap();
+        utils::fs::write_file(base.path(), "book.css", b"base css").unwrap();
+        utils::fs::write_file(base.path(), "book.js", b"base js").unwrap();
+        utils::fs::write_file(base.path(), "partials/footer.hbs", b"base footer").unwrap();
+        utils::fs::write_file(base.path(), "partials/nav.hbs", b"base nav").unwrap();
+        utils::fs::write_file(base.path(), "blocks.hbs", b"base blocks").unwrap();
+        let local = TempFileBuilder::new().prefix("mdbook").tempdir().unwrap();
+        utils::fs::write_file(local.path(), "book.css", b"local css").unwrap();
+        utils::fs::write_file(local.path(), "partials/footer.hbs", b"local footer").unwrap();
+        utils::fs::write_file(local.path(), "blocks.hbs", b"local blocks").unwrap();
+
+        let got = Theme::layered(&[base.path(), local.path(), Path::new("missing")]);
+
+        assert_eq!(got.css, b"local css");
+        assert_eq!(got.js, b"base js");
+        assert_eq!(got.index, INDEX);
+        assert_eq!(got.partials["footer"], b"local footer");
+        assert_eq!(got.partials["nav"], b"base nav");
+        assert_eq!(got.blocks, b"base blocks\nlocal blocks\n".to_vec());
+    }
+
+    #[test]
+    fn builtin_themes_are_layered_like_directories() {
+        let local = TempFileBuilder::new().prefix("mdbook").tempdir().unwrap();
+        utils::fs::write_file(local.path(), "book.css", b"local css").unwrap();
+        utils::fs::write_file(local.path(), "blocks.hbs", b"local blocks").unwrap();
+        let wide = builtin_theme("wide").unwrap();
+
+        let got = Theme::layered(&[
+            ThemeLayer::Builtin(wide),
+            ThemeLayer::from(local.path()),
+        ]);
+
+        let mut blocks = wide.files[0].1.to_vec();
+        blocks.extend_from_slice(b"\nlocal blocks\n");
+        assert_eq!(got.blocks, blocks);
+        assert_eq!(got.css, b"local css");
+        assert_eq!(got.js, JS);
+        let default = ThemeLayer::Builtin(builtin_theme("default").unwrap());
+        assert_eq!(Theme::layered(&[default]), Theme::default());
+        assert!(builtin_theme("missing").is_none());
+    }
 }
@@ -137,7 +137,14 @@ window.search = window.search || {};
             url.push("");
         }
 
-        return '<a href="' + url[0] + '?' + URL_MARK_PARAM + '=' + searchterms + '#' + url[1]
+        var href = path_to_root + url[0] + '?' + URL_MARK_PARAM + '=' + searchterms + '#' + url[1];
+        // When the whole book is on one page, every chapter has an anchor on it
+        if (window.page_anchors && page_anchors.hasOwnProperty(url[0])) {
+            var anchor = page_anchors[url[0]];
+            href = '#' + (url[1] ? anchor + '-' + url[1] : anchor);
+        }
+
+        return '<a href="' + href
             + '" aria-details="teaser_' + teaser_count + '">' + result.doc.breadcrumbs + '</a>'
             + '<span class="teaser" id="teaser_' + teaser_count + '" aria-label="Search Result Teaser">' 
             + teaser + '</span>';
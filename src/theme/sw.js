@@ -0,0 +1,48 @@
+"use strict";
+
+// mdBook puts a `PRECACHE` object in front of this script, containing a
+// version (which changes whenever the book does) and every file in the book,
+// relative to this script. They all get downloaded when the service worker is
+// installed, so the book can be read without a network connection afterwards.
+
+var CACHE_PREFIX = "mdbook " + self.registration.scope + " ";
+var CACHE_NAME = CACHE_PREFIX + PRECACHE.version;
+
+self.addEventListener("install", function (event) {
+    event.waitUntil(
+        caches.open(CACHE_NAME)
+            .then(function (cache) { return cache.addAll(PRECACHE.files); })
+            .then(function () { return self.skipWaiting(); })
+    );
+});
+
+self.addEventListener("activate", function (event) {
+    // Throw away whatever was cached for older versions of the book
+    event.waitUntil(
+        caches.keys()
+            .then(function (names) {
+                return Promise.all(names
+                    .filter(function (name) {
+                        return name.indexOf(CACHE_PREFIX) === 0 && name !== CACHE_NAME;
+                    })
+                    .map(function (name) { return caches.delete(name); }));
+            })
+            .then(function () { return self.clients.claim(); })
+    );
+});
+
+self.addEventListener("fetch", function (event) {
+    if (event.request.method !== "GET") { return; }
+
+    // Directories are served by their index.html
+    var url = new URL(event.request.url);
+    if (url.pathname.slice(-1) === "/") {
+        url.pathname += "index.html";
+    }
+
+    event.respondWith(
+        caches.open(CACHE_NAME)
+            .then(function (cache) { return cache.match(url.href, { ignoreSearch: true }); })
+            .then(function (response) { return response || fetch(event.request); })
+    );
+});
@@ -1,4 +1,6 @@
 use errors::*;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::ffi::OsStr;
 use std::fs::{self, File};
 use std::io::{Read, Write};
 use std::path::{Component, Path, PathBuf};
@@ -78,6 +80,134 @@ pub fn path_to_root<P: Into<PathBuf>>(path: P) -> String {
         })
 }
 
+/// Find the file a chapter was loaded from, relative to the source directory.
+/// This is usually just the chapter's path, except for `README.md` files which
+/// the "index" preprocessor renamed to `index.md`.
+pub fn chapter_source_path(src_dir: &Path, chapter: &Path) -> PathBuf {
+    if chapter.file_name() == Some(OsStr::new("index.md")) && !src_dir.join(chapter).exists() {
+        let readme = chapter.with_file_name("README.md");
+        if src_dir.join(&readme).exists() {
+            return readme;
+        }
+    }
+
+    chapter.to_path_buf()
+}
+
+/// Work out which file a relative link from `file` points to, relative to
+/// the root of the book (e.g. `../second.md` from `first/nested.md` gives
+/// `second.md`). Returns `None` if the link leaves the book.
+pub fn resolve_link(file: &Path, link: &str) -> Option<PathBuf> {
+    let mut resolved = PathBuf::new();
+    let base = file.parent().unwrap_or_else(|| Path::new(""));
+
+    for component in base.join(link).components() {
+        match component {
+            Component::Normal(part) => resolved.push(part),
+            Component::ParentDir => {
+                if !resolved.pop() {
+                    return None;
+                }
+            }
+            Component::CurDir => {}
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+
+    Some(resolved)
+}
+
+/// List every file in a directory and its subdirectories (relative to it,
+/// with forward slashes), leaving out hidden files and directories.
+pub fn visible_files(dir: &Path) -> Result<Vec<String>> {
+    fn collect(root: &Path, dir: &Path, files: &mut Vec<String>) -> Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let hidden = path.file_name()
+                .and_then(|name| name.to_str())
+                .map_or(true, |name| name.starts_with('.'));
+            if hidden {
+                continue;
+            }
+
+            if path.is_dir() {
+                collect(root, &path, files)?;
+            } else {
+                let relative = path.strip_prefix(root).expect("Files are inside the root");
+                files.push(normalize_path(&relative.to_string_lossy()));
+            }
+        }
+
+        Ok(())
+    }
+
+    let mut files = Vec::new();
+    collect(dir, dir, &mut files)?;
+    files.sort();
+    Ok(files)
+}
+
+/// The file in the source directory listing files to exclude, one
+/// `.gitignore`-style pattern per line.
+pub const IGNORE_FILE: &str = ".mdbookignore";
+
+/// The files in a book's source directory which mdBook should leave alone:
+/// those matching `build.exclude`, any other patterns the caller passes in or
+/// the patterns in `.mdbookignore`. Patterns follow the same rules as a
+/// `.gitignore`, so `fixtures/` only matches directories and `!keep.psd`
+/// brings back a file an earlier pattern excluded.
+#[derive(Debug)]
+pub struct ExcludedFiles {
+    src_dir: PathBuf,
+    matcher: Gitignore,
+}
+
+impl ExcludedFiles {
+    /// Read `.mdbookignore` (if there is one) and add `patterns` after it.
+    pub fn new<S: AsRef<str>>(src_dir: &Path, patterns: &[S]) -> Result<ExcludedFiles> {
+        let mut builder = GitignoreBuilder::new(src_dir);
+
+        let ignore_file = src_dir.join(IGNORE_FILE);
+        if ignore_file.is_file() {
+            if let Some(e) = builder.add(&ignore_file) {
+                return Err(e).chain_err(|| format!("Unable to read {}", ignore_file.display()));
+            }
+        }
+
+        for pattern in patterns {
+            let pattern = pattern.as_ref();
+            builder
+                .add_line(None, pattern)
+                .chain_err(|| format!("Invalid exclude pattern \"{}\"", pattern))?;
+        }
+
+        let matcher = builder
+            .build()
+            .chain_err(|| "Unable to build the list of excluded files")?;
+
+        Ok(ExcludedFiles {
+            src_dir: src_dir.to_path_buf(),
+            matcher,
+        })
+    }
+
+    /// Should `path` (either absolute or relative to the source directory) be
+    /// left alone? Files outside the source directory never are, while
+    /// `.mdbookignore` itself always is.
+    pub fn is_excluded(&self, path: &Path) -> bool {
+        let full_path = self.src_dir.join(path);
+        let relative = match full_path.strip_prefix(&self.src_dir) {
+            Ok(relative) if relative != Path::new("") => relative,
+            _ => return false,
+        };
+
+        relative == Path::new(IGNORE_FILE)
+            || self.matcher
+                .matched_path_or_any_parents(relative, full_path.is_dir())
+                .is_ignore()
+    }
+}
+
 /// This function creates a file and returns it. But before creating the file
 /// it checks every directory in the path to see if it exists,
 /// and if it does not it will be created.
@@ -124,70 +254,72 @@ pub fn copy_files_except_ext(
         ext_blacklist
     );
 
-    // Check that from and to are different
-    if from == to {
-        return Ok(());
-    }
-
-    for entry in fs::read_dir(from)? {
-        let entry = entry?;
-        let metadata = entry.metadata()?;
+    copy_files_except(from, to, |path| {
+        let blacklisted = path.extension()
+            .and_then(OsStr::to_str)
+            .map_or(false, |ext| ext_blacklist.contains(&ext));
+        blacklisted || (!recursive && from.join(path).is_dir())
+    })
+}
 
-        // If the entry is a dir and the recursive option is enabled, call itself
-        if metadata.is_dir() && recursive {
-            if entry.path() == to.to_path_buf() {
+/// List the files in `from` and its subdirectories which should be copied to
+/// `to`, relative to `from`, leaving out those `skip` returns `true` for.
+/// `skip` is given paths relative to `from`, and nothing inside a skipped
+/// directory gets listed.
+pub fn files_except<F>(from: &Path, to: &Path, skip: F) -> Result<Vec<PathBuf>>
+where
+    F: Fn(&Path) -> bool,
+{
+    fn collect(
+        root: &Path,
+        dir: &Path,
+        to: &Path,
+        skip: &dyn Fn(&Path) -> bool,
+        files: &mut Vec<PathBuf>,
+    ) -> Result<()> {
+        for entry in fs::read_dir(root.join(dir))? {
+            let entry = entry?;
+            let relative = dir.join(entry.file_name());
+            // The destination might be inside the source directory
+            if root.join(&relative) == to || skip(&relative) {
                 continue;
             }
 
-            // check if output dir already exists
-            if !to.join(entry.file_name()).exists() {
-                fs::create_dir(&to.join(entry.file_name()))?;
+            if entry.metadata()?.is_dir() {
+                collect(root, &relative, to, skip, files)?;
+            } else {
+                files.push(relative);
             }
+        }
 
-            copy_files_except_ext(
-                &from.join(entry.file_name()),
-                &to.join(entry.file_name()),
-                true,
-                ext_blacklist,
-            )?;
-        } else if metadata.is_file() {
-            // Check if it is in the blacklist
-            if let Some(ext) = entry.path().extension() {
-                if ext_blacklist.contains(&ext.to_str().unwrap()) {
-                    continue;
-                }
-            }
-            debug!(
-                "creating path for file: {:?}",
-                &to.join(
-                    entry
-                        .path()
-                        .file_name()
-                        .expect("a file should have a file name...")
-                )
-            );
-
-            debug!(
-                "Copying {:?} to {:?}",
-                entry.path(),
-                &to.join(
-                    entry
-                        .path()
-                        .file_name()
-                        .expect("a file should have a file name...")
-                )
-            );
-            fs::copy(
-                entry.path(),
-                &to.join(
-                    entry
-                        .path()
-                        .file_name()
-                        .expect("a file should have a file name..."),
-                ),
-            )?;
+        Ok(())
+    }
+
+    let mut files = Vec::new();
+    if from != to {
+        collect(from, Path::new(""), to, &skip, &mut files)?;
+    }
+    Ok(files)
+}
+
+/// Copies every file and directory in `from` to `to`, except those `skip`
+/// returns `true` for. `skip` is given paths relative to `from`, and nothing
+/// inside a skipped directory gets copied.
+pub fn copy_files_except<F>(from: &Path, to: &Path, skip: F) -> Result<()>
+where
+    F: Fn(&Path) -> bool,
+{
+    debug!("Copying all files from {} to {}", from.display(), to.display());
+    for relative in files_except(from, to, skip)? {
+        let path = from.join(&relative);
+        let target = to.join(&relative);
+        debug!("Copying {} to {}", path.display(), target.display());
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
         }
+        fs::copy(&path, &target).chain_err(|| format!("Unable to copy {}", path.display()))?;
     }
+
     Ok(())
 }
 
@@ -195,8 +327,63 @@ pub fn copy_files_except_ext(
 mod tests {
     extern crate tempfile;
 
-    use super::copy_files_except_ext;
+    use super::{copy_files_except, copy_files_except_ext, resolve_link, ExcludedFiles};
     use std::fs;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn links_are_resolved_relative_to_the_file() {
+        let inputs = vec![
+            ("intro.md", "other.md", Some("other.md")),
+            ("first/nested.md", "../second.md", Some("second.md")),
+            ("first/nested.md", "./deeper/chapter.md", Some("first/deeper/chapter.md")),
+            ("first/nested.md", "../../outside.md", None),
+        ];
+
+        for (file, link, should_be) in inputs {
+            assert_eq!(resolve_link(Path::new(file), link), should_be.map(PathBuf::from));
+        }
+    }
+
+    #[test]
+    fn excluded_files_follow_gitignore_rules() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let src = tmp.path();
+        super::write_file(src, ".mdbookignore", b"# editor files\n*.swp\nfixtures/\n").unwrap();
+        super::write_file(src, "fixtures/data.json", b"").unwrap();
+        super::write_file(src, "nested/fixtures", b"").unwrap();
+
+        let excluded = ExcludedFiles::new(src, &["*.psd", "!keep.psd"]).unwrap();
+
+        assert!(excluded.is_excluded(Path::new(".mdbookignore")));
+        assert!(excluded.is_excluded(Path::new("nested/.chapter.md.swp")));
+        assert!(excluded.is_excluded(Path::new("fixtures")));
+        assert!(excluded.is_excluded(&src.join("fixtures/data.json")));
+        assert!(excluded.is_excluded(Path::new("images/cover.psd")));
+        assert!(!excluded.is_excluded(Path::new("images/keep.psd")));
+        assert!(!excluded.is_excluded(Path::new("nested/fixtures")));
+        assert!(!excluded.is_excluded(Path::new("chapter.md")));
+        assert!(!excluded.is_excluded(&tmp.path().parent().unwrap().join("other.swp")));
+    }
+
+    #[test]
+    fn skipped_files_and_directories_are_not_copied() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let src = tmp.path().join("src");
+        for file in &["chapter.md", "image.png", "fixtures/data.json", "nested/keep.txt"] {
+            super::write_file(&src, file, b"").unwrap();
+        }
+
+        let dest = tmp.path().join("dest");
+        copy_files_except(&src, &dest, |path| {
+            path.extension().map_or(false, |ext| ext == "md") || path == Path::new("fixtures")
+        }).unwrap();
+
+        assert!(dest.join("image.png").exists());
+        assert!(dest.join("nested/keep.txt").exists());
+        assert!(!dest.join("chapter.md").exists());
+        assert!(!dest.join("fixtures").exists());
+    }
 
     #[test]
     fn copy_files_except_ext_test() {
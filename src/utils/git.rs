@@ -0,0 +1,111 @@
+//! Asking `git` when files were last changed.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Starts each commit in the log, so it can't be confused with a file name.
+const COMMIT_MARKER: char = '\u{1e}';
+
+/// When a file was last committed to, and by whom.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LastModified {
+    /// When the change was committed, as seconds since the Unix epoch. This is
+    /// the commit date rather than the author date, as after a rebase or a
+    /// cherry-pick it's the one which says when the content landed.
+    pub timestamp: i64,
+    /// The same time in ISO 8601 format (e.g. `2018-05-27T14:03:12+02:00`).
+    pub datetime: String,
+    /// Just the day (e.g. `2018-05-27`).
+    pub date: String,
+    /// The name of the change's author.
+    pub author: String,
+}
+
+/// Find the last commit which touched each file in `dir` (and its
+/// sub-directories), by path relative to `dir`. Files which aren't tracked
+/// are left out, and if `dir` isn't in a git repository (or `git` couldn't be
+/// run) nothing is returned.
+///
+/// This only runs `git log` once, no matter how many files there are.
+pub fn last_modified(dir: &Path) -> Option<BTreeMap<PathBuf, LastModified>> {
+    let output = Command::new("git")
+        .args(&["-c", "core.quotepath=off", "log", "--relative", "--name-only"])
+        .arg(format!("--format={}%ct%n%cI%n%an", COMMIT_MARKER))
+        .args(&["--", "."])
+        .current_dir(dir)
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+
+    Some(parse_log(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Parse the output of `git log --name-only` with the format used above. The
+/// log starts with the newest commit, so the first one to list a file is the
+/// one which last changed it.
+fn parse_log(log: &str) -> BTreeMap<PathBuf, LastModified> {
+    let mut files = BTreeMap::new();
+
+    for commit in log.split(COMMIT_MARKER) {
+        let mut lines = commit.lines();
+        let modified = match parse_commit(&mut lines) {
+            Some(modified) => modified,
+            None => continue,
+        };
+
+        for file in lines.map(str::trim).filter(|file| !file.is_empty()) {
+            files
+                .entry(PathBuf::from(file))
+                .or_insert_with(|| modified.clone());
+        }
+    }
+
+    files
+}
+
+fn parse_commit<'a, I: Iterator<Item = &'a str>>(lines: &mut I) -> Option<LastModified> {
+    let timestamp = lines.next()?.trim().parse().ok()?;
+    let datetime = lines.next()?.trim().to_string();
+    let author = lines.next()?.trim().to_string();
+
+    Some(LastModified {
+        timestamp,
+        date: datetime.chars().take_while(|&ch| ch != 'T').collect(),
+        datetime,
+        author,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_git_log_output() {
+        let log = "\u{1e}1527422592\n2018-05-27T14:03:12+02:00\nJane Doe\n\nfirst.md\nnested/second.md\n\
+                   \u{1e}1527000000\n2018-05-22T16:40:00+02:00\nJohn Doe\n\nfirst.md\nthird.md\n";
+
+        let got = parse_log(log);
+
+        let jane = LastModified {
+            timestamp: 1527422592,
+            datetime: String::from("2018-05-27T14:03:12+02:00"),
+            date: String::from("2018-05-27"),
+            author: String::from("Jane Doe"),
+        };
+        assert_eq!(got[Path::new("first.md")], jane);
+        assert_eq!(got[Path::new("nested/second.md")], jane);
+        assert_eq!(got[Path::new("third.md")].author, "John Doe");
+        assert_eq!(got.len(), 3);
+    }
+
+    #[test]
+    fn an_empty_history_has_no_files() {
+        assert!(parse_log("").is_empty());
+    }
+}
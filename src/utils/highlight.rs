@@ -0,0 +1,166 @@
+//! Syntax highlighting while rendering, for `output.html.highlight = "server"`.
+//!
+//! Code is marked up with the same classes `highlight.js` would use (e.g.
+//! `hljs-keyword`), so the theme's stylesheets work no matter where the
+//! highlighting happens.
+
+/// Was mdBook compiled with support for highlighting code while rendering?
+pub fn is_supported() -> bool {
+    cfg!(feature = "highlight")
+}
+
+/// Highlight some code written in `lang` (e.g. `rust`), returning `None` if
+/// the language isn't known.
+#[cfg(feature = "highlight")]
+pub fn highlight(lang: &str, code: &str) -> Option<String> {
+    use syntect::parsing::{ParseState, ScopeStack};
+    use syntect::util::LinesWithEndings;
+
+    let syntax = SYNTAXES.find_syntax_by_token(lang)?;
+    let mut state = ParseState::new(syntax);
+    let mut stack = ScopeStack::new();
+    let mut spans = Spans::default();
+
+    for line in LinesWithEndings::from(code) {
+        let ops = state.parse_line(line, &SYNTAXES).ok()?;
+        let mut start = 0;
+
+        for (end, op) in ops {
+            spans.push(&line[start..end], class_for(&stack));
+            start = end;
+            stack.apply(&op).ok()?;
+        }
+        spans.push(&line[start..], class_for(&stack));
+    }
+
+    Some(spans.finish())
+}
+
+#[cfg(not(feature = "highlight"))]
+pub fn highlight(_lang: &str, _code: &str) -> Option<String> {
+    None
+}
+
+#[cfg(feature = "highlight")]
+lazy_static! {
+    static ref SYNTAXES: ::syntect::parsing::SyntaxSet =
+        ::syntect::parsing::SyntaxSet::load_defaults_newlines();
+
+    /// The `highlight.js` class to use for each kind of scope, most specific
+    /// first. `None` means the text shouldn't be highlighted.
+    static ref CLASSES: Vec<(::syntect::parsing::Scope, Option<&'static str>)> = [
+        ("comment", Some("hljs-comment")),
+        ("string.regexp", Some("hljs-regexp")),
+        ("string", Some("hljs-string")),
+        ("constant.numeric", Some("hljs-number")),
+        ("constant.character", Some("hljs-string")),
+        ("constant.other.placeholder", Some("hljs-string")),
+        ("constant", Some("hljs-literal")),
+        ("keyword.operator", None),
+        ("keyword", Some("hljs-keyword")),
+        ("storage", Some("hljs-keyword")),
+        ("variable.language", Some("hljs-keyword")),
+        ("variable.parameter", Some("hljs-params")),
+        ("entity.name.tag", Some("hljs-name")),
+        ("entity.name.section", Some("hljs-section")),
+        ("entity.name", Some("hljs-title")),
+        ("entity.other.attribute-name", Some("hljs-attribute")),
+        ("support", Some("hljs-built_in")),
+        ("meta.annotation", Some("hljs-meta")),
+        ("meta.attribute", Some("hljs-meta")),
+        ("meta.preprocessor", Some("hljs-meta")),
+        ("markup.heading", Some("hljs-section")),
+        ("markup.inserted", Some("hljs-addition")),
+        ("markup.deleted", Some("hljs-deletion")),
+        ("markup.bold", Some("hljs-strong")),
+        ("markup.italic", Some("hljs-emphasis")),
+        ("markup.underline.link", Some("hljs-link")),
+    ].iter()
+        .map(|&(scope, class)| (::syntect::parsing::Scope::new(scope).unwrap(), class))
+        .collect();
+}
+
+/// Find the class for the innermost scope we know about.
+#[cfg(feature = "highlight")]
+fn class_for(stack: &::syntect::parsing::ScopeStack) -> Option<&'static str> {
+    for scope in stack.as_slice().iter().rev() {
+        let class = CLASSES
+            .iter()
+            .find(|&&(prefix, _)| prefix.is_prefix_of(*scope));
+
+        if let Some(&(_, class)) = class {
+            return class;
+        }
+    }
+
+    None
+}
+
+/// Builds up the highlighted HTML, merging neighbouring pieces of text with
+/// the same class and keeping newlines outside of the `<span>`s so every line
+/// of the output starts with the code itself.
+#[cfg(feature = "highlight")]
+#[derive(Default)]
+struct Spans {
+    html: String,
+    class: Option<&'static str>,
+    text: String,
+}
+
+#[cfg(feature = "highlight")]
+impl Spans {
+    fn push(&mut self, text: &str, class: Option<&'static str>) {
+        for (i, piece) in text.split('\n').enumerate() {
+            if i > 0 {
+                self.flush();
+                self.html.push('\n');
+            }
+            if piece.is_empty() {
+                continue;
+            }
+            if class != self.class {
+                self.flush();
+                self.class = class;
+            }
+            self.text.push_str(piece);
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.text.is_empty() {
+            return;
+        }
+
+        let text = super::escape_html(&self.text);
+        match self.class {
+            Some(class) => self.html
+                .push_str(&format!("<span class=\"{}\">{}</span>", class, text)),
+            None => self.html.push_str(&text),
+        }
+        self.text.clear();
+    }
+
+    fn finish(mut self) -> String {
+        self.flush();
+        self.html
+    }
+}
+
+#[cfg(all(test, feature = "highlight"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn code_gets_highlight_js_classes() {
+        let got = highlight("rust", "// Hi\nfn main() {\n    let x = \"a < b\";\n}\n").unwrap();
+
+        assert!(got.starts_with("<span class=\"hljs-comment\">// Hi</span>\n"));
+        assert!(got.contains("<span class=\"hljs-keyword\">fn</span>"));
+        assert!(got.contains("<span class=\"hljs-string\">&quot;a &lt; b&quot;</span>"));
+    }
+
+    #[test]
+    fn unknown_languages_are_left_alone() {
+        assert_eq!(highlight("not-a-language", "foo"), None);
+    }
+}
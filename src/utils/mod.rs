@@ -1,7 +1,10 @@
 #![allow(missing_docs)] // FIXME: Document this
 
 pub mod fs;
+pub mod git;
+pub mod highlight;
 mod string;
+use config::Playpen;
 use errors::Error;
 use regex::Regex;
 
@@ -9,6 +12,9 @@ use pulldown_cmark::{html, Event, Options, Parser, Tag, OPTION_ENABLE_FOOTNOTES,
                      OPTION_ENABLE_TABLES};
 
 use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::iter;
+use std::path::{Path, PathBuf};
 
 pub use self::string::{RangeArgument, take_lines};
 
@@ -68,8 +74,152 @@ pub fn id_from_content(content: &str) -> String {
     normalize_id(trimmed)
 }
 
+/// Splits an explicit `{#custom-id}` off the end of a header's content,
+/// returning the remaining content and the ID (if there was one).
+pub fn split_custom_id(content: &str) -> (&str, Option<&str>) {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r"\s*\{#([\w\-:.]+)\}\s*$").unwrap();
+    }
+
+    match RE.captures(content) {
+        Some(caps) => {
+            let whole = caps.get(0).expect("The whole match is always present");
+            let id = caps.get(1).expect("The regex has one group");
+            (&content[..whole.start()], Some(id.as_str()))
+        }
+        None => (content, None),
+    }
+}
+
+/// Splits a relative link to another markdown file (e.g.
+/// `../other/chapter.md#section`) into the file's path and the fragment, if
+/// there is one. Anything else, like external URLs, absolute paths or links to
+/// other kinds of files, gives `None`.
+pub fn split_md_link(dest: &str) -> Option<(&str, Option<&str>)> {
+    split_link(dest, &[".md"])
+}
+
+/// Like [`split_md_link`], but also accepts links to the page a chapter gets
+/// rendered to (e.g. `../other/chapter.html#section`).
+fn split_chapter_link(dest: &str) -> Option<(&str, Option<&str>)> {
+    split_link(dest, &[".md", ".html"])
+}
+
+fn split_link<'a>(dest: &'a str, extensions: &[&str]) -> Option<(&'a str, Option<&'a str>)> {
+    if !is_relative_link(dest) {
+        return None;
+    }
+
+    let (path, fragment) = match dest.find('#') {
+        Some(i) => (&dest[..i], Some(&dest[i + 1..])),
+        None => (dest, None),
+    };
+
+    if extensions.iter().any(|extension| path.ends_with(extension)) {
+        Some((path, fragment))
+    } else {
+        None
+    }
+}
+
+/// Undo the escaping of characters like spaces (`%20`) in a URL.
+pub fn percent_decode(url: &str) -> String {
+    let bytes = url.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        let byte = match (bytes[i], url.get(i + 1..i + 3)) {
+            (b'%', Some(hex)) => u8::from_str_radix(hex, 16).ok(),
+            _ => None,
+        };
+        match byte {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Does the link point to another file relative to the current one?
+pub fn is_relative_link(dest: &str) -> bool {
+    !dest.is_empty() && !dest.starts_with('/') && !dest.starts_with('#') && !has_scheme(dest)
+}
+
+/// Does the link start with a URL scheme (`https:`, `mailto:`, ...)?
+fn has_scheme(dest: &str) -> bool {
+    match dest.find(':') {
+        Some(i) => {
+            i > 0
+                && dest[..i]
+                    .chars()
+                    .all(|ch| ch.is_ascii_alphanumeric() || ch == '+' || ch == '-' || ch == '.')
+        }
+        None => false,
+    }
+}
+
+/// Every header ID [`render_markdown_with_options`] would give the headers in
+/// a document.
+///
+/// [`render_markdown_with_options`]: fn.render_markdown_with_options.html
+pub fn header_ids(text: &str) -> HashSet<String> {
+    let mut header_ids = HashMap::new();
+    render_markdown_with_options(text, &RenderOptions::default(), &mut header_ids);
+
+    // Every ID in use is a key, see `unique_id()`
+    header_ids.keys().cloned().collect()
+}
+
+/// Extra settings for [`render_markdown_with_options`].
+///
+/// [`render_markdown_with_options`]: fn.render_markdown_with_options.html
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RenderOptions<'a> {
+    /// Use "smart quotes" instead of the usual `"` character.
+    pub curly_quotes: bool,
+    /// Where the rendered page will live (e.g. `first/index.html`), used when
+    /// reporting problems with the document.
+    pub path: Option<&'a str>,
+    /// Wrap runnable Rust code blocks so they can be sent to the playpen.
+    pub playpen: Option<&'a Playpen>,
+    /// The chapter being rendered (e.g. `first/nested.md`), if it is being
+    /// rendered onto a page along with other chapters.
+    pub chapter: Option<&'a Path>,
+    /// An anchor for every chapter on the page, keyed by the chapter's path.
+    /// Header IDs get prefixed with the current chapter's anchor, and links
+    /// between chapters on the page are turned into links within the page.
+    pub chapter_anchors: Option<&'a HashMap<PathBuf, String>>,
+    /// Highlight code blocks while rendering instead of leaving it to
+    /// `highlight.js` in the browser.
+    pub highlight: bool,
+}
+
 /// Wrapper around the pulldown-cmark parser for rendering markdown to HTML.
 pub fn render_markdown(text: &str, curly_quotes: bool) -> String {
+    let options = RenderOptions {
+        curly_quotes,
+        ..Default::default()
+    };
+
+    render_markdown_with_options(text, &options, &mut HashMap::new())
+}
+
+/// Render markdown to HTML, wrapping every header in an anchor. The
+/// `header_ids` are used to make sure each anchor is unique, letting you share
+/// them between several documents which end up on the same page.
+pub fn render_markdown_with_options(
+    text: &str,
+    options: &RenderOptions,
+    header_ids: &mut HashMap<String, usize>,
+) -> String {
     let mut s = String::with_capacity(text.len() * 3 / 2);
 
     let mut opts = Options::empty();
@@ -77,11 +227,22 @@ pub fn render_markdown(text: &str, curly_quotes: bool) -> String {
     opts.insert(OPTION_ENABLE_FOOTNOTES);
 
     let p = Parser::new_ext(text, opts);
-    let mut converter = EventQuoteConverter::new(curly_quotes);
+    let mut converter = EventQuoteConverter::new(options.curly_quotes);
     let events = p.map(clean_codeblock_headers)
+                  .map(|event| fix_links(event, options))
                   .map(|event| converter.convert(event));
+    let events = wrap_code_blocks(events, options);
+    let id_prefix = chapter_anchor(options);
+    // Duplicate IDs are reported when a chapter gets its own page, not again
+    // when it's included in another one (or only scanned for its IDs)
+    let report_to = if options.chapter.is_none() {
+        options.path
+    } else {
+        None
+    };
+    let events = build_header_links(events, report_to, id_prefix, header_ids);
 
-    html::push_html(&mut s, events);
+    html::push_html(&mut s, events.into_iter());
     s
 }
 
@@ -131,6 +292,392 @@ fn clean_codeblock_headers(event: Event) -> Event {
     }
 }
 
+/// The anchor of the chapter being rendered, if it is sharing its page with
+/// other chapters.
+fn chapter_anchor<'a>(options: &RenderOptions<'a>) -> Option<&'a str> {
+    match (options.chapter, options.chapter_anchors) {
+        (Some(chapter), Some(anchors)) => anchors.get(chapter).map(|anchor| anchor.as_str()),
+        _ => None,
+    }
+}
+
+/// Points relative links to other markdown files (`other.md#section`) at the
+/// page they get rendered to (`other.html#section`). When several chapters
+/// share a page, links to chapters on the page (by their `.md` or `.html`
+/// name) become links within the page, other relative links are made relative
+/// to the page and footnotes are namespaced with the chapter's anchor.
+fn fix_links<'a>(event: Event<'a>, options: &RenderOptions) -> Event<'a> {
+    match event {
+        Event::FootnoteReference(name) => Event::FootnoteReference(footnote_name(name, options)),
+        Event::Start(Tag::FootnoteDefinition(name)) => {
+            Event::Start(Tag::FootnoteDefinition(footnote_name(name, options)))
+        }
+        Event::End(Tag::FootnoteDefinition(name)) => {
+            Event::End(Tag::FootnoteDefinition(footnote_name(name, options)))
+        }
+        Event::Start(Tag::Link(dest, title)) => match fix_link(&dest, options) {
+            Some(fixed) => Event::Start(Tag::Link(Cow::from(fixed), title)),
+            None => Event::Start(Tag::Link(dest, title)),
+        },
+        Event::Start(Tag::Image(dest, title)) => match fix_link(&dest, options) {
+            Some(fixed) => Event::Start(Tag::Image(Cow::from(fixed), title)),
+            None => Event::Start(Tag::Image(dest, title)),
+        },
+        _ => event,
+    }
+}
+
+fn footnote_name<'a>(name: Cow<'a, str>, options: &RenderOptions) -> Cow<'a, str> {
+    match chapter_anchor(options) {
+        Some(anchor) => Cow::from(format!("{}-{}", anchor, name)),
+        None => name,
+    }
+}
+
+fn fix_link(dest: &str, options: &RenderOptions) -> Option<String> {
+    if let (Some(chapter), Some(anchors)) = (options.chapter, options.chapter_anchors) {
+        if dest.starts_with('#') {
+            return chapter_anchor(options).map(|anchor| format!("#{}-{}", anchor, &dest[1..]));
+        }
+
+        if let Some((path, fragment)) = split_chapter_link(dest) {
+            let target = fs::resolve_link(chapter, path).map(|target| target.with_extension("md"));
+            if let Some(anchor) = target.as_ref().and_then(|target| anchors.get(target)) {
+                return Some(match fragment {
+                    Some(fragment) if !fragment.is_empty() => format!("#{}-{}", anchor, fragment),
+                    _ => format!("#{}", anchor),
+                });
+            }
+        }
+
+        if is_relative_link(dest) {
+            let dest = fix_md_link(dest).unwrap_or_else(|| dest.to_string());
+            let fixed = match fs::resolve_link(chapter, &dest) {
+                Some(resolved) => resolved,
+                None => chapter.parent().unwrap_or_else(|| Path::new("")).join(dest),
+            };
+            return Some(fs::normalize_path(&fixed.to_string_lossy()));
+        }
+    }
+
+    fix_md_link(dest)
+}
+
+fn fix_md_link(dest: &str) -> Option<String> {
+    split_md_link(dest).map(|(path, fragment)| {
+        let mut fixed = format!("{}.html", &path[..path.len() - ".md".len()]);
+        if let Some(fragment) = fragment {
+            fixed.push('#');
+            fixed.push_str(fragment);
+        }
+        fixed
+    })
+}
+
+/// Escape some text so it can be inserted into HTML, the same way
+/// pulldown-cmark would.
+pub fn escape_html(text: &str) -> String {
+    let mut s = String::with_capacity(text.len());
+    html::push_html(&mut s, iter::once(Event::Text(Cow::from(text))));
+    s
+}
+
+/// Renders code blocks, turning rustdoc-style annotations like
+/// `rust,should_panic` into space-separated classes, (if a playpen is
+/// configured) wrapping runnable Rust snippets so they can be sent to the
+/// playpen and (if asked to) highlighting the code.
+fn wrap_code_blocks<'a, I>(events: I, options: &RenderOptions) -> Vec<Event<'a>>
+where
+    I: IntoIterator<Item = Event<'a>>,
+{
+    let mut out = Vec::new();
+    let mut classes: Option<Vec<String>> = None;
+    let mut code = String::new();
+
+    for event in events {
+        match event {
+            Event::Start(Tag::CodeBlock(ref info)) => {
+                let mut tags = info.split(',').filter(|tag| !tag.is_empty());
+                let mut list = Vec::new();
+                if let Some(lang) = tags.next() {
+                    list.push(format!("language-{}", lang));
+                }
+                list.extend(tags.map(String::from));
+
+                classes = Some(list);
+                code.clear();
+            }
+            Event::Text(ref text) if classes.is_some() => code.push_str(text),
+            Event::End(Tag::CodeBlock(_)) => {
+                let classes = classes.take().unwrap_or_default();
+                out.extend(code_block_events(classes, &code, options));
+            }
+            other => if classes.is_none() {
+                out.push(other);
+            },
+        }
+    }
+
+    out
+}
+
+fn code_block_events<'a>(
+    mut classes: Vec<String>,
+    code: &str,
+    options: &RenderOptions,
+) -> Vec<Event<'a>> {
+    let has_class = |name: &str| classes.iter().any(|class| class == name);
+
+    let runnable = (has_class("language-rust") && !has_class("ignore"))
+        || has_class("mdbook-runnable");
+
+    let (pre_tag, code) = match options.playpen {
+        Some(playpen) if runnable => {
+            if playpen.editable && has_class("editable") || code.contains("fn main")
+                || code.contains("quick_main!")
+            {
+                ("<pre class=\"playpen\">", code.to_string())
+            } else {
+                // we need to inject our own main
+                let (attrs, code) = partition_source(code);
+                let code = format!(
+                    "\n# #![allow(unused_variables)]\n{}#fn main() {{\n{}#}}",
+                    attrs, code
+                );
+                ("<pre class=\"playpen\">", code)
+            }
+        }
+        _ => ("<pre>", code.to_string()),
+    };
+
+    // editable snippets are handed to the Ace editor as plain text
+    let highlighted = if options.highlight && !has_class("editable") {
+        classes
+            .iter()
+            .find(|class| class.starts_with("language-"))
+            .and_then(|class| highlight_code(&class["language-".len()..], &code))
+    } else {
+        None
+    };
+    if highlighted.is_some() {
+        classes.push(String::from("hljs"));
+    }
+
+    let code_tag = if classes.is_empty() {
+        String::from("<code>")
+    } else {
+        format!("<code class=\"{}\">", escape_html(&classes.join(" ")))
+    };
+
+    vec![
+        Event::Html(Cow::from(format!("{}{}", pre_tag, code_tag))),
+        match highlighted {
+            Some(html) => Event::Html(Cow::from(html)),
+            None => Event::Text(Cow::from(code)),
+        },
+        Event::Html(Cow::from("</code></pre>\n")),
+    ]
+}
+
+/// Highlight a code block, making sure the `#` marking a hidden line of Rust
+/// stays at the very start of the line (outside any `<span>`) so `book.js` can
+/// still find it.
+fn highlight_code(lang: &str, code: &str) -> Option<String> {
+    if lang != "rust" {
+        return highlight::highlight(lang, code);
+    }
+
+    let hidden = code.split('\n').map(is_hidden_line).collect::<Vec<_>>();
+    let visible = code.split('\n')
+        .map(|line| {
+            if !is_hidden_line(line) {
+                return line.to_string();
+            }
+            let rest = line.trim_start();
+            let indent = &line[..line.len() - rest.len()];
+            let rest = if rest.starts_with("# ") {
+                &rest[2..]
+            } else {
+                &rest[1..]
+            };
+            format!("{}{}", indent, rest)
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let html = highlight::highlight(lang, &visible)?;
+    let lines = html.split('\n')
+        .zip(hidden)
+        .map(|(line, hidden)| if hidden { format!("# {}", line) } else { line.to_string() })
+        .collect::<Vec<_>>();
+
+    Some(lines.join("\n"))
+}
+
+/// Is this a line of Rust which `book.js` hides (e.g. `# use std::fs;`), as
+/// opposed to an attribute like `#[derive(Debug)]`?
+fn is_hidden_line(line: &str) -> bool {
+    let line = line.trim_start();
+    line.starts_with('#') && !line.starts_with("#[") && !line.starts_with("#!")
+}
+
+fn partition_source(s: &str) -> (String, String) {
+    let mut after_header = false;
+    let mut before = String::new();
+    let mut after = String::new();
+
+    for line in s.lines() {
+        let trimline = line.trim();
+        let header = trimline.chars().all(|c| c.is_whitespace()) || trimline.starts_with("#![");
+        if !header || after_header {
+            after_header = true;
+            after.push_str(line);
+            after.push('\n');
+        } else {
+            before.push_str(line);
+            before.push('\n');
+        }
+    }
+
+    (before, after)
+}
+
+/// Wraps every header in a link so people can link to sections directly,
+/// making sure each one gets its own ID by appending an auto-incremented
+/// number (if necessary). Headers ending in `{#custom-id}` use that as their ID
+/// instead of one derived from their content, and if a `report_to` page is
+/// given any of those used more than once are reported against it.
+fn build_header_links<'a, I>(
+    events: I,
+    report_to: Option<&str>,
+    id_prefix: Option<&str>,
+    header_ids: &mut HashMap<String, usize>,
+) -> Vec<Event<'a>>
+where
+    I: IntoIterator<Item = Event<'a>>,
+{
+    // Headers are rendered to HTML first, so their explicit IDs are known
+    // before any IDs get generated
+    let mut items = Vec::new();
+    let mut header: Option<Vec<Event<'a>>> = None;
+    for event in events {
+        match event {
+            Event::Start(Tag::Header(_)) => header = Some(Vec::new()),
+            Event::End(Tag::Header(level)) => {
+                let mut html = String::new();
+                html::push_html(&mut html, header.take().unwrap_or_default().into_iter());
+                items.push(Err((level, html)));
+            }
+            other => match header {
+                Some(ref mut inner) => inner.push(other),
+                None => items.push(Ok(other)),
+            },
+        }
+    }
+
+    let ids = {
+        let headers = items.iter().filter_map(|item| match *item {
+            Err((_, ref html)) => Some(html.as_str()),
+            Ok(_) => None,
+        });
+        allocate_header_ids(headers, report_to, id_prefix, header_ids)
+    };
+    let mut ids = ids.into_iter();
+
+    items
+        .into_iter()
+        .map(|item| match item {
+            Ok(event) => event,
+            Err((level, html)) => {
+                let (text, _) = split_custom_id(&html);
+                let id = ids.next().expect("Every header has an ID");
+                Event::Html(Cow::from(wrap_header_with_link(level, text, &id)))
+            }
+        })
+        .collect()
+}
+
+/// The IDs [`render_markdown_with_options`] gives a document's headers, from
+/// their text in the order they appear. Headers ending in `{#custom-id}` use
+/// that ID and the rest get one derived from their content.
+///
+/// [`render_markdown_with_options`]: fn.render_markdown_with_options.html
+pub fn ids_for_headers<'a, I>(headers: I) -> Vec<String>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    allocate_header_ids(headers, None, None, &mut HashMap::new())
+}
+
+fn allocate_header_ids<'a, I>(
+    headers: I,
+    report_to: Option<&str>,
+    id_prefix: Option<&str>,
+    header_ids: &mut HashMap<String, usize>,
+) -> Vec<String>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let headers = headers.into_iter().map(split_custom_id).collect::<Vec<_>>();
+    let prefixed = |id: &str| match id_prefix {
+        Some(prefix) => format!("{}-{}", prefix, id),
+        None => id.to_string(),
+    };
+
+    // Explicit IDs are kept as they are, so links to them stay stable. They
+    // are reserved up front so generated IDs never take them.
+    let mut custom_ids = HashSet::new();
+    for custom_id in headers.iter().filter_map(|&(_, custom_id)| custom_id) {
+        let id = prefixed(custom_id);
+        let duplicate = !custom_ids.insert(id.clone()) || header_ids.contains_key(&id);
+        if let (true, Some(path)) = (duplicate, report_to) {
+            warn!("The header ID \"{}\" is used more than once in {}", custom_id, path);
+        }
+        header_ids.entry(id).or_insert(1);
+    }
+
+    headers
+        .into_iter()
+        .map(|(text, custom_id)| match custom_id {
+            Some(custom_id) => prefixed(custom_id),
+            None => unique_id(&prefixed(&id_from_content(text)), header_ids),
+        })
+        .collect()
+}
+
+/// Number `raw_id` (`foo`, `foo-1`, `foo-2`, ...) until it gives an ID which
+/// isn't in use yet, and mark that ID as used.
+pub fn unique_id(raw_id: &str, header_ids: &mut HashMap<String, usize>) -> String {
+    loop {
+        let id = {
+            let id_count = header_ids.entry(raw_id.to_string()).or_insert(0);
+            let id = match *id_count {
+                0 => raw_id.to_string(),
+                other => format!("{}-{}", raw_id, other),
+            };
+            *id_count += 1;
+            id
+        };
+
+        if id == raw_id {
+            return id;
+        }
+        if !header_ids.contains_key(&id) {
+            header_ids.insert(id.clone(), 1);
+            return id;
+        }
+    }
+}
+
+fn wrap_header_with_link(level: i32, content: &str, id: &str) -> String {
+    format!(
+        r##"<a class="header" href="#{id}" id="{id}"><h{level}>{text}</h{level}></a>
+"##,
+        level = level,
+        id = id,
+        text = content
+    )
+}
 
 fn convert_quotes_to_curly(original_text: &str) -> String {
     // We'll consider the start to be "whitespace".
@@ -229,7 +776,7 @@ more text with spaces
 "#;
 
             let expected =
-                r#"<pre><code class="language-rust,no_run,should_panic,property_3"></code></pre>
+                r#"<pre><code class="language-rust no_run should_panic property_3"></code></pre>
 "#;
             assert_eq!(render_markdown(input, false), expected);
             assert_eq!(render_markdown(input, true), expected);
@@ -243,7 +790,7 @@ more text with spaces
 "#;
 
             let expected =
-                r#"<pre><code class="language-rust,no_run,,,should_panic,,property_3"></code></pre>
+                r#"<pre><code class="language-rust no_run should_panic property_3"></code></pre>
 "#;
             assert_eq!(render_markdown(input, false), expected);
             assert_eq!(render_markdown(input, true), expected);
@@ -270,8 +817,348 @@ more text with spaces
         }
     }
 
+    mod render_markdown_with_options {
+        use super::super::{render_markdown_with_options, RenderOptions};
+        use config::Playpen;
+        use std::collections::HashMap;
+
+        fn render_playpen(input: &str, editable: bool) -> String {
+            let playpen = Playpen {
+                editable: editable,
+                ..Default::default()
+            };
+            let options = RenderOptions {
+                playpen: Some(&playpen),
+                ..Default::default()
+            };
+            render_markdown_with_options(input, &options, &mut HashMap::new())
+        }
+
+        #[test]
+        fn headers_are_wrapped_in_links() {
+            let inputs = vec![
+                (
+                    "blah blah\n# Foo",
+                    r##"<p>blah blah</p>
+<a class="header" href="#foo" id="foo"><h1>Foo</h1></a>
+"##,
+                ),
+                (
+                    "# Foo",
+                    r##"<a class="header" href="#foo" id="foo"><h1>Foo</h1></a>
+"##,
+                ),
+                (
+                    "### Foo^bar",
+                    r##"<a class="header" href="#foobar" id="foobar"><h3>Foo^bar</h3></a>
+"##,
+                ),
+                (
+                    "####",
+                    r##"<a class="header" href="#" id=""><h4></h4></a>
+"##,
+                ),
+                (
+                    "#### *Hï*",
+                    r##"<a class="header" href="#hï" id="hï"><h4><em>Hï</em></h4></a>
+"##,
+                ),
+                (
+                    "# Foo\n### Foo",
+                    r##"<a class="header" href="#foo" id="foo"><h1>Foo</h1></a>
+<a class="header" href="#foo-1" id="foo-1"><h3>Foo</h3></a>
+"##,
+                ),
+            ];
+
+            let options = RenderOptions {
+                path: Some("./some_chapter/some_section.html"),
+                ..Default::default()
+            };
+
+            for (src, should_be) in inputs {
+                let got = render_markdown_with_options(src, &options, &mut HashMap::new());
+                assert_eq!(got, should_be);
+            }
+        }
+
+        #[test]
+        fn headers_can_have_custom_ids() {
+            let options = RenderOptions {
+                path: Some("chapter.html"),
+                ..Default::default()
+            };
+            let input = "## Custom ID\n## Some *Heading* {#custom-id}\n## Another {#custom-id-1}";
+
+            let got = render_markdown_with_options(input, &options, &mut HashMap::new());
+
+            // Explicit IDs win over generated ones, even later in the chapter
+            let expected = r##"<a class="header" href="#custom-id-2" id="custom-id-2"><h2>Custom ID</h2></a>
+<a class="header" href="#custom-id" id="custom-id"><h2>Some <em>Heading</em></h2></a>
+<a class="header" href="#custom-id-1" id="custom-id-1"><h2>Another</h2></a>
+"##;
+            assert_eq!(got, expected);
+
+            // Duplicates are reported rather than renamed
+            let got = render_markdown_with_options(
+                "## One {#same}\n## Two {#same}",
+                &options,
+                &mut HashMap::new(),
+            );
+            assert_eq!(got.matches(r#"id="same""#).count(), 2);
+        }
+
+        #[test]
+        fn header_ids_can_be_shared_between_documents() {
+            let options = RenderOptions::default();
+            let mut ids = HashMap::new();
+
+            let first = render_markdown_with_options("# Foo", &options, &mut ids);
+            let second = render_markdown_with_options("# Foo", &options, &mut ids);
+
+            assert!(first.contains(r#"id="foo""#));
+            assert!(second.contains(r#"id="foo-1""#));
+        }
+
+        #[test]
+        fn anchor_links_are_left_alone() {
+            let options = RenderOptions {
+                path: Some("first/index.html"),
+                ..Default::default()
+            };
+            let input = "[here](#foo) [there](other.html#foo) note[^1]\n\n[^1]: a note";
+
+            let got = render_markdown_with_options(input, &options, &mut HashMap::new());
+
+            assert!(got.contains(r##"<a href="#foo">here</a>"##));
+            assert!(got.contains(r##"<a href="other.html#foo">there</a>"##));
+            assert!(got.contains(r##"<a href="#1">1</a>"##));
+            assert!(got.contains(r##"<div class="footnote-definition" id="1">"##));
+        }
+
+        #[test]
+        fn html_in_code_is_left_alone() {
+            let input = r#"
+```html
+<h1>Foo</h1>
+<base href="../">
+```
+"#;
+            let expected = r#"<pre><code class="language-html">&lt;h1&gt;Foo&lt;/h1&gt;
+&lt;base href=&quot;../&quot;&gt;
+</code></pre>
+"#;
+
+            assert_eq!(render_playpen(input, false), expected);
+        }
+
+        #[test]
+        fn rust_code_blocks_get_a_main_function() {
+            let input = r#"
+```rust
+#![feature(foo)]
+let x = 1 < 2;
+```
+"#;
+            let expected = r#"<pre class="playpen"><code class="language-rust">
+# #![allow(unused_variables)]
+#![feature(foo)]
+#fn main() {
+let x = 1 &lt; 2;
+#}</code></pre>
+"#;
+
+            assert_eq!(render_playpen(input, false), expected);
+        }
+
+        #[test]
+        fn rust_code_blocks_with_main_are_wrapped_as_is() {
+            let input = r#"
+```rust,no_run
+fn main() {}
+```
+"#;
+            let expected = r#"<pre class="playpen"><code class="language-rust no_run">fn main() {}
+</code></pre>
+"#;
+
+            assert_eq!(render_playpen(input, false), expected);
+        }
+
+        #[test]
+        fn editable_code_blocks_are_wrapped_as_is() {
+            let input = r#"
+```rust,editable
+let x = 1;
+```
+"#;
+            let expected = r#"<pre class="playpen"><code class="language-rust editable">let x = 1;
+</code></pre>
+"#;
+
+            assert_eq!(render_playpen(input, true), expected);
+        }
+
+        #[test]
+        fn ignored_and_non_rust_code_blocks_are_not_runnable() {
+            let input = r#"
+```rust,ignore
+let x = 1;
+```
+```python
+x = 1
+```
+"#;
+            let expected = r#"<pre><code class="language-rust ignore">let x = 1;
+</code></pre>
+<pre><code class="language-python">x = 1
+</code></pre>
+"#;
+
+            assert_eq!(render_playpen(input, false), expected);
+        }
+
+        #[test]
+        fn links_to_markdown_files_point_at_the_rendered_page() {
+            let input = "[a](../other/chapter.md#section) [b](chapter.md) \
+                         [c](https://example.com/README.md) [d](image.png) [e](#foo.md)";
+            let options = RenderOptions::default();
+
+            let got = render_markdown_with_options(input, &options, &mut HashMap::new());
+
+            assert!(got.contains(r##"<a href="../other/chapter.html#section">a</a>"##));
+            assert!(got.contains(r##"<a href="chapter.html">b</a>"##));
+            assert!(got.contains(r##"<a href="https://example.com/README.md">c</a>"##));
+            assert!(got.contains(r##"<a href="image.png">d</a>"##));
+            assert!(got.contains(r##"<a href="#foo.md">e</a>"##));
+        }
+
+        #[test]
+        #[cfg(feature = "highlight")]
+        fn code_can_be_highlighted_while_rendering() {
+            let input = r#"
+```rust
+# use std::fs;
+#[derive(Debug)]
+struct Foo;
+```
+"#;
+            let options = RenderOptions {
+                highlight: true,
+                ..Default::default()
+            };
+            let expected = r#"<pre><code class="language-rust hljs"># <span class="hljs-keyword">use</span> std::fs;
+<span class="hljs-meta">#[derive(Debug)]</span>
+<span class="hljs-keyword">struct</span> <span class="hljs-title">Foo</span>;
+</code></pre>
+"#;
+
+            let got = render_markdown_with_options(input, &options, &mut HashMap::new());
+            assert_eq!(got, expected);
+        }
+    }
+
+    mod chapters_sharing_a_page {
+        use super::super::{render_markdown_with_options, RenderOptions};
+        use std::collections::HashMap;
+        use std::path::{Path, PathBuf};
+
+        fn render_on_print_page(chapter: &str, text: &str) -> String {
+            let mut anchors = HashMap::new();
+            anchors.insert(PathBuf::from("intro.md"), String::from("intro"));
+            anchors.insert(PathBuf::from("first.md"), String::from("first"));
+            anchors.insert(PathBuf::from("first/nested.md"), String::from("first-nested"));
+
+            let mut header_ids = HashMap::new();
+            for anchor in anchors.values() {
+                header_ids.insert(anchor.clone(), 1);
+            }
+
+            let options = RenderOptions {
+                chapter: Some(Path::new(chapter)),
+                chapter_anchors: Some(&anchors),
+                ..Default::default()
+            };
+            render_markdown_with_options(text, &options, &mut header_ids)
+        }
+
+        #[test]
+        fn header_ids_are_prefixed_with_the_chapter_anchor() {
+            let got = render_on_print_page("first/nested.md", "# Intro\n## Custom {#custom}");
+
+            assert!(got.contains(r##"href="#first-nested-intro" id="first-nested-intro""##));
+            assert!(got.contains(r##"href="#first-nested-custom" id="first-nested-custom""##));
+        }
+
+        #[test]
+        fn header_ids_never_clash_with_chapter_anchors() {
+            let got = render_on_print_page("first.md", "## Nested");
+
+            assert!(got.contains(r##"href="#first-nested-1" id="first-nested-1""##));
+        }
+
+        #[test]
+        fn footnotes_are_prefixed_with_the_chapter_anchor() {
+            let got = render_on_print_page("intro.md", "Text[^note]\n\n[^note]: A footnote");
+
+            assert!(got.contains(r##"<a href="#intro-note">1</a>"##));
+            assert!(got.contains(r##"<div class="footnote-definition" id="intro-note">"##));
+        }
+
+        #[test]
+        fn links_between_chapters_stay_on_the_page() {
+            let got = render_on_print_page(
+                "first/nested.md",
+                "[a](../intro.md) [b](../intro.md#some-section) [c](#below) \
+                 [d](../missing.md) ![e](img.png) [f](https://example.com/) \
+                 [g](../intro.html#other-section)",
+            );
+
+            assert!(got.contains(r##"<a href="#intro">a</a>"##));
+            assert!(got.contains(r##"<a href="#intro-some-section">b</a>"##));
+            assert!(got.contains(r##"<a href="#first-nested-below">c</a>"##));
+            assert!(got.contains(r##"<a href="missing.html">d</a>"##));
+            assert!(got.contains(r##"<img src="first/img.png" alt="e" />"##));
+            assert!(got.contains(r##"<a href="https://example.com/">f</a>"##));
+            assert!(got.contains(r##"<a href="#intro-other-section">g</a>"##));
+        }
+    }
+
     mod html_munging {
-        use super::super::{id_from_content, normalize_id};
+        use super::super::{header_ids, id_from_content, normalize_id, split_custom_id,
+                           split_md_link};
+
+        #[test]
+        fn it_splits_markdown_links() {
+            assert_eq!(split_md_link("chapter.md"), Some(("chapter.md", None)));
+            assert_eq!(
+                split_md_link("../a/b.md#some-section"),
+                Some(("../a/b.md", Some("some-section")))
+            );
+            assert_eq!(split_md_link("chapter.html"), None);
+            assert_eq!(split_md_link("#section.md"), None);
+            assert_eq!(split_md_link("/absolute.md"), None);
+            assert_eq!(split_md_link("https://example.com/README.md"), None);
+            assert_eq!(split_md_link("mailto:someone@README.md"), None);
+        }
+
+        #[test]
+        fn it_finds_every_header_id() {
+            let ids = header_ids("# Foo\n## Foo\n## Bar {#custom}\n```\n# Not a header\n```");
+
+            let mut ids: Vec<_> = ids.into_iter().collect();
+            ids.sort();
+            assert_eq!(ids, vec!["custom", "foo", "foo-1"]);
+        }
+
+        #[test]
+        fn it_splits_off_custom_ids() {
+            assert_eq!(split_custom_id("Heading {#my-id}"), ("Heading", Some("my-id")));
+            assert_eq!(split_custom_id("Heading {#a.b:c_d} "), ("Heading", Some("a.b:c_d")));
+            assert_eq!(split_custom_id("Heading"), ("Heading", None));
+            assert_eq!(split_custom_id("Heading {#not an id}"), ("Heading {#not an id}", None));
+            assert_eq!(split_custom_id("{#id} Heading"), ("{#id} Heading", None));
+        }
 
         #[test]
         fn it_generates_anchors() {
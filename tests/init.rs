@@ -68,3 +68,17 @@ fn book_toml_isnt_required() {
 
     md.build().unwrap();
 }
+
+/// `mdbook init --theme` copies the templates along with the rest of the
+/// theme.
+#[test]
+fn copied_theme_includes_the_templates() {
+    let temp = TempFileBuilder::new().prefix("mdbook").tempdir().unwrap();
+
+    MDBook::init(temp.path()).copy_theme(true).build().unwrap();
+
+    let theme_dir = temp.path().join("src").join("theme");
+    for file in &["index.hbs", "redirect.hbs", "book.css", "book.js"] {
+        assert!(theme_dir.join(file).exists(), "{} doesn't exist", file);
+    }
+}
@@ -1,3 +1,4 @@
+extern crate handlebars;
 extern crate mdbook;
 #[macro_use]
 extern crate pretty_assertions;
@@ -21,6 +22,8 @@ use mdbook::errors::*;
 use mdbook::utils::fs::{file_to_string, write_file};
 use mdbook::config::Config;
 use mdbook::MDBook;
+use mdbook::renderer::HtmlHandlebars;
+use handlebars::{Handlebars, Helper, HelperResult, RenderContext};
 
 const BOOK_ROOT: &'static str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/dummy_book");
 const TOC_TOP_LEVEL: &[&'static str] = &[
@@ -83,32 +86,29 @@ fn check_correct_cross_links_in_nested_dir() {
 
     let first = temp.path().join("book").join("first");
     let links = vec![
-        r#"<base href="../">"#,
-        r#"href="intro.html""#,
-        r#"href="first/index.html""#,
-        r#"href="first/nested.html""#,
-        r#"href="second.html""#,
-        r#"href="conclusion.html""#,
+        r#"href="../intro.html""#,
+        r#"href="../first/index.html""#,
+        r#"href="../first/nested.html""#,
+        r#"href="../second.html""#,
+        r#"href="../conclusion.html""#,
+        r#"href="../book.css""#,
     ];
 
     let files_in_nested_dir = vec!["index.html", "nested.html"];
 
     for filename in files_in_nested_dir {
         assert_contains_strings(first.join(filename), &links);
+        assert_doesnt_contain_strings(first.join(filename), &["<base href="]);
     }
 
     assert_contains_strings(
         first.join("index.html"),
-        &[
-            r##"href="first/index.html#some-section" id="some-section""##,
-        ],
+        &[r##"href="#some-section" id="some-section""##],
     );
 
     assert_contains_strings(
         first.join("nested.html"),
-        &[
-            r##"href="first/nested.html#some-section" id="some-section""##,
-        ],
+        &[r##"href="#some-section" id="some-section""##],
     );
 }
 
@@ -367,8 +367,8 @@ fn by_default_mdbook_use_index_preprocessor_to_convert_readme_to_index() {
         .join("first")
         .join("index.html");
     let expected_strings = vec![
-        r#"href="first/index.html""#,
-        r#"href="second/index.html""#,
+        r#"href="../first/index.html""#,
+        r#"href="../second/index.html""#,
         "First README",
     ];
     assert_contains_strings(&first_index, &expected_strings);
@@ -402,6 +402,187 @@ fn theme_dir_overrides_work_correctly() {
     dummy_book::assert_contains_strings(built_index, &["This is a modified index.hbs!"]);
 }
 
+#[test]
+fn themes_can_add_partials_page_templates_and_helpers() {
+    let temp = DummyBook::new().build().unwrap();
+    let theme_dir = temp.path().join("theme");
+
+    let mut index = ::mdbook::theme::INDEX.to_vec();
+    index.extend_from_slice(b"\n{{> footer}}");
+    write_file(&theme_dir, "index.hbs", &index).unwrap();
+    write_file(&theme_dir, "partials/footer.hbs", b"<!-- footer for {{path}} -->").unwrap();
+    write_file(&theme_dir, "templates/landing.hbs", b"landing: {{shout chapter_title}}").unwrap();
+
+    let mut cfg = Config::default();
+    cfg.set("output.html.templates.landing", vec!["first/index.md"]).unwrap();
+    let mut md = MDBook::load_with_config(temp.path(), cfg).unwrap();
+
+    let mut renderer = HtmlHandlebars::new();
+    renderer.register_helper(
+        "shout",
+        |h: &Helper, _: &Handlebars, rc: &mut RenderContext| -> HelperResult {
+            let text = h.param(0).and_then(|p| p.value().as_str()).unwrap_or("");
+            rc.writer.write_all(text.to_uppercase().as_bytes())?;
+            Ok(())
+        },
+    );
+    md.with_renderer(renderer);
+    md.build().unwrap();
+
+    let landing = temp.path().join("book/first/index.html");
+    assert_eq!(file_to_string(landing).unwrap(), "landing: FIRST CHAPTER");
+    let nested = temp.path().join("book/first/nested.html");
+    assert_contains_strings(nested, &["<!-- footer for first/nested.md -->"]);
+}
+
+#[test]
+fn book_theme_is_layered_over_a_parent_theme() {
+    let temp = DummyBook::new().build().unwrap();
+    let parent_dir = temp.path().join("base-theme");
+    write_file(&parent_dir, "book.css", b"/* parent css */").unwrap();
+    write_file(&parent_dir, "book.js", b"/* parent js */").unwrap();
+    write_file(&parent_dir, "partials/body-end.hbs", b"<!-- parent body end -->").unwrap();
+    write_file(
+        &parent_dir,
+        "blocks.hbs",
+        b"{{#*inline \"head-extra\"}}<!-- parent head -->{{/inline}}",
+    ).unwrap();
+    let theme_dir = temp.path().join("theme");
+    write_file(&theme_dir, "book.css", b"/* local css */").unwrap();
+    write_file(
+        &theme_dir,
+        "blocks.hbs",
+        b"{{#*inline \"content-after\"}}<!-- after {{path}} -->{{/inline}}",
+    ).unwrap();
+
+    let mut cfg = Config::default();
+    cfg.set("output.html.parent-theme", "base-theme").unwrap();
+    let md = MDBook::load_with_config(temp.path(), cfg).unwrap();
+    md.build().unwrap();
+
+    let book_dir = temp.path().join("book");
+    assert_eq!(file_to_string(book_dir.join("book.css")).unwrap(), "/* local css */");
+    assert_eq!(file_to_string(book_dir.join("book.js")).unwrap(), "/* parent js */");
+    assert_contains_strings(
+        book_dir.join("first/nested.html"),
+        &[
+            "<!-- parent head -->\n    </head>",
+            "<!-- after first/nested.md -->",
+            "<!-- parent body end -->",
+        ],
+    );
+}
+
+#[test]
+fn a_theme_directory_is_used_on_its_own() {
+    let temp = DummyBook::new().build().unwrap();
+    write_file(&temp.path().join("theme"), "book.css", b"/* local css */").unwrap();
+    write_file(&temp.path().join("other-theme"), "book.js", b"/* other js */").unwrap();
+
+    let mut cfg = Config::default();
+    cfg.set("output.html.theme", "other-theme").unwrap();
+    let md = MDBook::load_with_config(temp.path(), cfg).unwrap();
+    md.build().unwrap();
+
+    let book_dir = temp.path().join("book");
+    assert_eq!(file_to_string(book_dir.join("book.js")).unwrap(), "/* other js */");
+    assert_ne!(file_to_string(book_dir.join("book.css")).unwrap(), "/* local css */");
+}
+
+#[test]
+fn builtin_themes_can_be_selected_by_name() {
+    let temp = DummyBook::new().build().unwrap();
+    write_file(&temp.path().join("theme"), "book.css", b"/* local css */").unwrap();
+
+    let mut cfg = Config::default();
+    cfg.set("output.html.theme", "wide").unwrap();
+    let md = MDBook::load_with_config(temp.path(), cfg).unwrap();
+    md.build().unwrap();
+
+    let book_dir = temp.path().join("book");
+    assert_contains_strings(book_dir.join("first/index.html"), &["max-width: none;"]);
+    assert_eq!(file_to_string(book_dir.join("book.css")).unwrap(), "/* local css */");
+
+    let mut cfg = Config::default();
+    cfg.set("output.html.parent-theme", "wide").unwrap();
+    let md = MDBook::load_with_config(temp.path(), cfg).unwrap();
+    md.build().unwrap();
+
+    assert_contains_strings(book_dir.join("first/index.html"), &["max-width: none;"]);
+    assert_eq!(file_to_string(book_dir.join("book.css")).unwrap(), "/* local css */");
+}
+
+#[test]
+fn missing_parent_themes_are_an_error() {
+    let temp = DummyBook::new().build().unwrap();
+
+    let mut cfg = Config::default();
+    cfg.set("output.html.parent-theme", "no-such-theme").unwrap();
+    let md = MDBook::load_with_config(temp.path(), cfg).unwrap();
+    assert!(md.build().is_err());
+}
+
+#[test]
+fn missing_images_fail_the_build_unless_allowed() {
+    let temp = DummyBook::new().build().unwrap();
+    write_file(
+        &temp.path().join("src"),
+        "conclusion.md",
+        b"# Conclusion\n\n![A chart](images/missing.png)\n",
+    ).unwrap();
+
+    let md = MDBook::load(temp.path()).unwrap();
+    assert!(md.build().is_err());
+
+    let mut cfg = Config::default();
+    cfg.set("output.html.images.deny-missing", false).unwrap();
+    let md = MDBook::load_with_config(temp.path(), cfg).unwrap();
+    md.build().unwrap();
+    assert!(temp.path().join("book/conclusion.html").exists());
+}
+
+#[test]
+fn single_file_books_cant_vendor_mathjax() {
+    let temp = DummyBook::new().build().unwrap();
+    write_file(temp.path(), "mathjax/MathJax.js", b"").unwrap();
+
+    let mut cfg = Config::default();
+    cfg.set("output.html.single-file", true).unwrap();
+    cfg.set("output.html.mathjax-support", true).unwrap();
+    cfg.set("output.html.math.mode", "vendored").unwrap();
+    cfg.set("output.html.math.mathjax-dir", "mathjax").unwrap();
+    let md = MDBook::load_with_config(temp.path(), cfg).unwrap();
+    assert!(md.build().is_err());
+}
+
+#[test]
+fn custom_color_schemes_are_listed_and_loaded() {
+    let temp = DummyBook::new().build().unwrap();
+    write_file(temp.path(), "schemes/solarized.css", b".solarized { --bg: #002b36; }").unwrap();
+
+    let mut cfg = Config::default();
+    cfg.set("output.html.default-theme", "rust").unwrap();
+    cfg.set("output.html.preferred-dark-theme", "solarized").unwrap();
+    cfg.set("output.html.color-schemes.solarized.name", "Solarized").unwrap();
+    cfg.set("output.html.color-schemes.solarized.css", "schemes/solarized.css").unwrap();
+    cfg.set("output.html.color-schemes.solarized.dark", true).unwrap();
+    let md = MDBook::load_with_config(temp.path(), cfg).unwrap();
+    md.build().unwrap();
+
+    let book_dir = temp.path().join("book");
+    assert!(book_dir.join("schemes/solarized.css").exists());
+    assert_contains_strings(
+        book_dir.join("first/nested.html"),
+        &[
+            r#"<body class="rust">"#,
+            r#"matches ? "solarized" : "rust";"#,
+            r#"id="rust" data-highlight="highlight">Rust <span class="default">(default)</span>"#,
+            r#"id="solarized" data-highlight="tomorrow-night">Solarized</button>"#,
+            r#"<link rel="stylesheet" href="../schemes/solarized.css">"#,
+        ],
+    );
+}
+
 #[cfg(feature = "search")]
 mod search {
     extern crate serde_json;